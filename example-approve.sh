@@ -0,0 +1,8 @@
+#!/usr/bin/env bash
+
+notify-send --wait \
+  --action=allow=Allow \
+  --action=deny=Deny \
+  "bwfs" \
+  "$BWFS_EXE ($BWFS_PID) wants to read $BWFS_FIELD of $BWFS_ITEM_NAME" |
+  grep -q '^allow$'
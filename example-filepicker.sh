@@ -16,7 +16,7 @@ file=$(cd $root && find . -type f | fzf)
 
 if [[ -f "$root/$file" ]]; then
   echo "Selected $file"
-  cat "$root/$file" | wl-copy
+  $bwfs copy "${file#./}"
 else
   exit 1
 fi
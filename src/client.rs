@@ -1,38 +1,40 @@
 use std::{
-    io::{Read, Write},
-    os::unix::net::UnixStream,
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{OpenOptions, Permissions},
+    io::{IsTerminal, Read, Write},
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        net::UnixStream,
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    str::FromStr,
+    time::Duration,
 };
 
 use anyhow::Context;
+use clap::ValueEnum;
+use dialoguer::{theme::ColorfulTheme, FuzzySelect};
+use time::OffsetDateTime;
 use tracing::debug;
+use uuid::Uuid;
 
 use crate::message::{Request, Response};
+use crate::server::clipboard::CLIPBOARD_ENV;
+use crate::server::mapfs::ItemSummary;
+use crate::template::Template;
+
+/// Seconds a copied value stays on the clipboard unless told otherwise.
+pub const DEFAULT_CLEAR_AFTER_S: u64 = 30;
 
 pub fn unlock(
     socket: String,
     no_refresh: bool,
     password_prompt: Option<String>,
 ) -> anyhow::Result<()> {
-    let password = if let Some(password_prompt) = password_prompt {
-        let mut cmd = Command::new(password_prompt);
-        cmd.stdin(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .stdout(Stdio::piped());
-        debug!(?cmd, "Prompting for password with custom script");
-        let password = cmd.output()?;
-        if password.status.success() {
-            String::from_utf8(password.stdout)?
-        } else {
-            anyhow::bail!(
-                "Password prompt failed with exit code {}",
-                password.status.code().unwrap_or(1)
-            );
-        }
-    } else {
-        debug!("Prompting for password with rpassword");
-        rpassword::prompt_password("Bitwarden password (input is hidden): ").unwrap()
-    };
+    let password = read_password(password_prompt)?;
     if password.is_empty() {
         eprintln!("Got empty password, skipping unlock");
         return Ok(());
@@ -54,6 +56,30 @@ pub fn unlock(
     Ok(())
 }
 
+/// Read the master password with the custom prompt script, or from the terminal without one.
+pub fn read_password(password_prompt: Option<String>) -> anyhow::Result<String> {
+    let password = if let Some(password_prompt) = password_prompt {
+        let mut cmd = Command::new(password_prompt);
+        cmd.stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .stdout(Stdio::piped());
+        debug!(?cmd, "Prompting for password with custom script");
+        let password = cmd.output()?;
+        if password.status.success() {
+            String::from_utf8(password.stdout)?
+        } else {
+            anyhow::bail!(
+                "Password prompt failed with exit code {}",
+                password.status.code().unwrap_or(1)
+            );
+        }
+    } else {
+        debug!("Prompting for password with rpassword");
+        rpassword::prompt_password("Bitwarden password (input is hidden): ")?
+    };
+    Ok(password)
+}
+
 pub fn lock(socket: String) -> anyhow::Result<()> {
     let request = Request::Lock;
     match send_msg(socket.clone(), request)? {
@@ -67,10 +93,20 @@ pub fn lock(socket: String) -> anyhow::Result<()> {
 pub fn status(socket: String) -> anyhow::Result<i32> {
     let request = Request::Status;
     match send_msg(socket, request)? {
-        Response::Status { locked } => {
+        Response::Status {
+            locked,
+            offline_since,
+        } => {
             if locked {
                 eprintln!("Locked");
                 Ok(1)
+            } else if let Some(since) = offline_since {
+                let age = OffsetDateTime::now_utc() - since;
+                eprintln!(
+                    "Unlocked (offline, stale snapshot from {} ago)",
+                    format_age(age)
+                );
+                Ok(0)
             } else {
                 eprintln!("Unlocked");
                 Ok(0)
@@ -89,6 +125,414 @@ pub fn refresh(socket: String) -> anyhow::Result<()> {
     Ok(())
 }
 
+pub fn audit_tail(socket: String, count: usize) -> anyhow::Result<()> {
+    match send_msg(socket, Request::AuditTail { count })? {
+        Response::Audit { entries } => {
+            for entry in entries {
+                println!("{entry}");
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// An environment variable to set to the contents of a file in the vault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub name: String,
+    /// Path of the file relative to the mountpoint.
+    pub path: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = anyhow::Error;
+
+    /// Parse from `NAME=PATH`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, path)) = s.split_once('=') else {
+            anyhow::bail!("Expected NAME=PATH, got {s:?}");
+        };
+        if name.is_empty() || path.is_empty() {
+            anyhow::bail!("Expected NAME=PATH, got {s:?}");
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// Read a TOML file of `NAME = "PATH"` pairs.
+fn read_env_file(path: &Path) -> anyhow::Result<Vec<EnvVar>> {
+    let content = std::fs::read_to_string(path).context(path.display().to_string())?;
+    let vars: BTreeMap<String, String> =
+        toml::from_str(&content).context(path.display().to_string())?;
+    Ok(vars
+        .into_iter()
+        .map(|(name, path)| EnvVar { name, path })
+        .collect())
+}
+
+/// Replace this process with `command`, with the given variables set from the vault.
+pub fn exec(
+    socket: String,
+    env: Vec<EnvVar>,
+    env_files: Vec<PathBuf>,
+    command: Vec<String>,
+) -> anyhow::Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        anyhow::bail!("No command given");
+    };
+    let mut vars = Vec::new();
+    for env_file in env_files {
+        vars.extend(read_env_file(&env_file)?);
+    }
+    // given last so they override the files
+    vars.extend(env);
+
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    for var in vars {
+        let value = read(socket.clone(), &var.path)
+            .with_context(|| format!("Failed to read {} for {}", var.path, var.name))?;
+        cmd.env(var.name, value);
+    }
+    debug!(program, "Executing command");
+    let error = cmd.exec();
+    Err(anyhow::Error::from(error).context(format!("Failed to run {program}")))
+}
+
+/// Render `template`, to stdout or atomically replacing `output`.
+///
+/// When watching, keeps running and renders again whenever an item the template refers to
+/// changes after a refresh.
+pub fn render(
+    socket: String,
+    template: PathBuf,
+    output: Option<PathBuf>,
+    mode: String,
+    watch: bool,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let mode = u32::from_str_radix(&mode, 8).context("Invalid mode")?;
+    let source = std::fs::read_to_string(&template).context(template.display().to_string())?;
+    let template = Template::parse(&source).context(template.display().to_string())?;
+    let paths: Vec<String> = template.paths().into_iter().map(str::to_owned).collect();
+    let render_once = || -> anyhow::Result<()> {
+        let rendered = template.render(|path| {
+            read(socket.clone(), path).with_context(|| format!("Failed to read {path}"))
+        })?;
+        match &output {
+            Some(output) => write_atomic(output, rendered.as_bytes(), mode)?,
+            None => std::io::stdout().write_all(rendered.as_bytes())?,
+        }
+        Ok(())
+    };
+    if !watch {
+        return render_once();
+    }
+
+    let mut rendered_at = None;
+    loop {
+        match modified(socket.clone(), paths.clone()) {
+            Ok(modified) if rendered_at.as_ref() != Some(&modified) => match render_once() {
+                Ok(()) => {
+                    eprintln!("Rendered {:?}", output.as_deref().unwrap_or(Path::new("-")));
+                    rendered_at = Some(modified);
+                }
+                Err(error) => eprintln!("Failed to render: {error:#}"),
+            },
+            Ok(_) => debug!("No changes to render"),
+            Err(error) => eprintln!("Failed to check for changes: {error:#}"),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Write `contents` to a new temporary file with `mode` then move it over `path`.
+///
+/// The temporary file gets a random name next to `path` and must not exist yet, so nobody can
+/// swap in a file or symlink of their own for it.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> anyhow::Result<()> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file", path.display()))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(".{}.tmp", Uuid::new_v4().simple()));
+    let tmp = path.with_file_name(tmp_name);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)
+        .context(path.display().to_string())?;
+    let written = (|| {
+        // the umask may have taken bits away from mode
+        file.set_permissions(Permissions::from_mode(mode))?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    written.context(path.display().to_string())
+}
+
+/// Print the file at `path`, or the whole item with `json`, or copy it to the clipboard.
+pub fn get(socket: String, path: String, json: bool, clipboard: bool) -> anyhow::Result<()> {
+    if clipboard {
+        return copy(socket, path, Duration::from_secs(DEFAULT_CLEAR_AFTER_S));
+    }
+    let request = if json {
+        Request::GetItem { path }
+    } else {
+        Request::Read { path }
+    };
+    let value = match send_msg(socket, request)? {
+        Response::Value { value } => value,
+        Response::Failure { reason } => anyhow::bail!("Failed to get: {reason}"),
+        _ => unreachable!(),
+    };
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(value.as_bytes())?;
+    if stdout.is_terminal() {
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Copy the file at `path` to the clipboard, restoring the previous contents after `clear_after`.
+///
+/// The server owns the timer, so this doesn't need to keep running.
+pub fn copy(socket: String, path: String, clear_after: Duration) -> anyhow::Result<()> {
+    let env = CLIPBOARD_ENV
+        .iter()
+        .filter_map(|name| Some(((*name).to_owned(), std::env::var(name).ok()?)))
+        .collect();
+    let request = Request::Copy {
+        path: path.clone(),
+        clear_after_s: clear_after.as_secs(),
+        env,
+    };
+    match send_msg(socket, request)? {
+        Response::Success => eprintln!(
+            "Copied {path} to the clipboard for {}s",
+            clear_after.as_secs()
+        ),
+        Response::Failure { reason } => anyhow::bail!("Failed to copy: {reason}"),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// What to do with the value chosen by [`pick`].
+#[derive(Debug)]
+pub enum PickAction {
+    Print,
+    Copy,
+    /// Run the command with the value set as the named environment variable.
+    Exec {
+        name: String,
+        command: Vec<String>,
+    },
+}
+
+/// Fuzzy find an item and one of its fields, unlocking first if needed, then act on its value.
+pub fn pick(
+    socket: String,
+    password_prompt: Option<String>,
+    action: PickAction,
+) -> anyhow::Result<()> {
+    if is_locked(socket.clone())? {
+        unlock(socket.clone(), false, password_prompt)?;
+    }
+    let items = match send_msg(socket.clone(), Request::List)? {
+        Response::Items { items } => items,
+        Response::Failure { reason } => anyhow::bail!("Failed to list items: {reason}"),
+        _ => unreachable!(),
+    };
+    if items.is_empty() {
+        anyhow::bail!("No items to pick from");
+    }
+
+    let theme = ColorfulTheme::default();
+    let labels: Vec<_> = items.iter().map(item_label).collect();
+    let Some(index) = FuzzySelect::with_theme(&theme)
+        .with_prompt("Item")
+        .items(&labels)
+        .default(0)
+        .interact_opt()?
+    else {
+        return Ok(());
+    };
+    let item = &items[index];
+    let mut fields: Vec<_> = item.fields.keys().cloned().collect();
+    // most likely wanted first
+    fields.sort_by_key(|f| {
+        ["password", "totp", "username"]
+            .iter()
+            .position(|p| p == f)
+            .unwrap_or(usize::MAX)
+    });
+    let Some(index) = FuzzySelect::with_theme(&theme)
+        .with_prompt("Field")
+        .items(&fields)
+        .default(0)
+        .interact_opt()?
+    else {
+        return Ok(());
+    };
+    let path = item.fields[&fields[index]].clone();
+
+    match action {
+        PickAction::Print => get(socket, path, false, false),
+        PickAction::Copy => copy(socket, path, Duration::from_secs(DEFAULT_CLEAR_AFTER_S)),
+        PickAction::Exec { name, command } => {
+            exec(socket, vec![EnvVar { name, path }], Vec::new(), command)
+        }
+    }
+}
+
+/// Print the paths of items with a login URI matching `url`.
+pub fn match_uri(socket: String, url: String) -> anyhow::Result<()> {
+    let paths = match send_msg(socket, Request::MatchUri { uri: url.clone() })? {
+        Response::Paths { paths } => paths,
+        Response::Failure { reason } => anyhow::bail!("Failed to match {url}: {reason}"),
+        _ => unreachable!(),
+    };
+    if paths.is_empty() {
+        anyhow::bail!("No items match {url}");
+    }
+    for path in paths {
+        println!("{path}");
+    }
+    Ok(())
+}
+
+/// Operations git asks of a credential helper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GitCredentialOperation {
+    /// Look up credentials for a URL.
+    Get,
+    /// Save credentials that worked, ignored as the vault is only read.
+    Store,
+    /// Forget credentials that were rejected, ignored as the vault is only read.
+    Erase,
+}
+
+/// Act as a git credential helper, answering `get` with a matching login from the vault.
+pub fn git_credential(socket: String, operation: GitCredentialOperation) -> anyhow::Result<()> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    if operation != GitCredentialOperation::Get {
+        debug!(?operation, "Ignoring git credential operation");
+        return Ok(());
+    }
+    let attrs = parse_credential(&input);
+    let Some(url) = credential_url(&attrs) else {
+        debug!(?attrs, "Not enough to look up a git credential");
+        return Ok(());
+    };
+    let request = Request::GitCredential {
+        url,
+        username: attrs.get("username").cloned(),
+    };
+    let (username, password) = match send_msg(socket, request)? {
+        Response::Credential { username, password } => (username, password),
+        Response::Failure { reason } => {
+            // leave git to try other helpers or prompt
+            eprintln!("bwfs: {reason}");
+            return Ok(());
+        }
+        _ => unreachable!(),
+    };
+    let mut out = String::new();
+    for (key, value) in [("username", username), ("password", Some(password))] {
+        let Some(value) = value else {
+            continue;
+        };
+        if value.contains(['\n', '\0']) {
+            anyhow::bail!("The {key} can't be passed to git as it contains a newline");
+        }
+        out.push_str(&format!("{key}={value}\n"));
+    }
+    std::io::stdout().write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// Parse the `key=value` lines git sends a credential helper, up to a blank line.
+fn parse_credential(input: &str) -> BTreeMap<String, String> {
+    input
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// The URL git wants credentials for, the path only being given with `credential.useHttpPath`.
+fn credential_url(attrs: &BTreeMap<String, String>) -> Option<String> {
+    if let Some(url) = attrs.get("url") {
+        return Some(url.clone());
+    }
+    let protocol = attrs.get("protocol")?;
+    let host = attrs.get("host")?;
+    let path = attrs.get("path").map_or("", String::as_str);
+    Some(format!("{protocol}://{host}/{path}"))
+}
+
+fn item_label(item: &ItemSummary) -> String {
+    let mut label = item.path.clone();
+    if let Some(username) = &item.username {
+        label.push_str(&format!(" ({username})"));
+    }
+    for uri in &item.uris {
+        label.push(' ');
+        label.push_str(uri);
+    }
+    label
+}
+
+fn is_locked(socket: String) -> anyhow::Result<bool> {
+    match send_msg(socket, Request::Status)? {
+        Response::Status { locked, .. } => Ok(locked),
+        Response::Failure { reason } => anyhow::bail!("Failed to get status: {reason}"),
+        _ => unreachable!(),
+    }
+}
+
+fn modified(socket: String, paths: Vec<String>) -> anyhow::Result<Vec<Option<OffsetDateTime>>> {
+    match send_msg(socket, Request::Modified { paths })? {
+        Response::Modified { modified } => Ok(modified),
+        Response::Failure { reason } => Err(anyhow::anyhow!(reason)),
+        _ => unreachable!(),
+    }
+}
+
+fn read(socket: String, path: &str) -> anyhow::Result<String> {
+    let request = Request::Read {
+        path: path.to_owned(),
+    };
+    match send_msg(socket, request)? {
+        Response::Value { value } => Ok(value),
+        Response::Failure { reason } => Err(anyhow::anyhow!(reason)),
+        _ => unreachable!(),
+    }
+}
+
+fn format_age(age: time::Duration) -> String {
+    if age.whole_days() > 0 {
+        format!("{}d {}h", age.whole_days(), age.whole_hours() % 24)
+    } else if age.whole_hours() > 0 {
+        format!("{}h {}m", age.whole_hours(), age.whole_minutes() % 60)
+    } else {
+        format!("{}m", age.whole_minutes())
+    }
+}
+
 fn send_msg(socket: String, request: Request) -> anyhow::Result<Response> {
     let mut stream = UnixStream::connect(&socket).context(socket.clone())?;
     debug!(socket, "Connected to server");
@@ -102,3 +546,89 @@ fn send_msg(socket: String, request: Request) -> anyhow::Result<Response> {
     let res = serde_json::from_str(&response_json)?;
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_env_var() {
+        assert_eq!(
+            "DB_PASS=prod/db/password".parse::<EnvVar>().unwrap(),
+            EnvVar {
+                name: "DB_PASS".to_owned(),
+                path: "prod/db/password".to_owned(),
+            }
+        );
+        assert!("DB_PASS".parse::<EnvVar>().is_err());
+        assert!("=prod/db/password".parse::<EnvVar>().is_err());
+        assert!("DB_PASS=".parse::<EnvVar>().is_err());
+    }
+
+    #[test]
+    fn parse_env_file() {
+        let path = std::env::temp_dir().join(format!("bwfs-env-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "DB_USER = \"prod/db/username\"\nDB_PASS = \"prod/db/password\"\n",
+        )
+        .unwrap();
+        let vars = read_env_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            vars,
+            [
+                EnvVar {
+                    name: "DB_PASS".to_owned(),
+                    path: "prod/db/password".to_owned(),
+                },
+                EnvVar {
+                    name: "DB_USER".to_owned(),
+                    path: "prod/db/username".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn git_credential_input() {
+        let attrs = parse_credential(
+            "protocol=https\nhost=git.example.com:8443\npath=team/repo.git\nusername=bob\n\nignored=1\n",
+        );
+        assert_eq!(attrs.len(), 4);
+        assert_eq!(attrs["username"], "bob");
+        assert_eq!(
+            credential_url(&attrs).as_deref(),
+            Some("https://git.example.com:8443/team/repo.git")
+        );
+
+        let attrs = parse_credential("protocol=https\nhost=github.com\n");
+        assert_eq!(
+            credential_url(&attrs).as_deref(),
+            Some("https://github.com/")
+        );
+        assert_eq!(credential_url(&parse_credential("protocol=https\n")), None);
+    }
+
+    #[test]
+    fn write_atomic_replaces() {
+        let dir = std::env::temp_dir().join(format!("bwfs-write-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("secrets.env");
+        std::fs::write(&path, "old").unwrap();
+        // a file left at the old fixed temporary name is neither followed nor clobbered
+        std::os::unix::fs::symlink(dir.join("elsewhere"), dir.join("secrets.env.tmp")).unwrap();
+
+        write_atomic(&path, b"new", 0o640).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["secrets.env", "secrets.env.tmp"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
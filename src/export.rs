@@ -0,0 +1,373 @@
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io::Write,
+    os::unix::fs::{symlink, DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use fuser::FUSE_ROOT_ID;
+use tracing::{debug, info};
+
+use crate::client::read_password;
+use crate::server::{
+    approval::Approver,
+    audit::{AuditLog, AuditLogRef, AuditSink},
+    backend::VaultBackend,
+    bwclient::StatusKind,
+    current_ids,
+    layout::Layout,
+    mapfs::{FSEntry, MapFS},
+    policy::Policy,
+    urimatch::{UriMatcher, DEFAULT_PUBLIC_SUFFIX_LIST},
+    BackendArgs,
+};
+
+/// Exported directories are only accessible by the current user.
+const DIR_MODE: u16 = 0o700;
+/// Exported files are only readable and writable by the current user.
+const FILE_MODE: u16 = 0o600;
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// What to write the vault tree as.
+    #[clap(long, value_enum, default_value_t = ExportFormat::Dir)]
+    format: ExportFormat,
+
+    /// Where to write the export, a directory for `dir` or a file for `tar` and `json`.
+    ///
+    /// The directory must be empty or not exist yet. Without this, `tar` and `json` are written
+    /// to stdout.
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    backend: BackendArgs,
+
+    /// Filter results to those in the folders listed.
+    #[clap(long, value_delimiter = ',')]
+    folders: Vec<String>,
+
+    /// How to lay out each item below its folder.
+    #[clap(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+
+    /// Custom password prompt script, not used if the vault is already unlocked.
+    ///
+    /// Must output the password onto stdout, stderr will be presented to the user.
+    #[clap(long)]
+    password_prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// A directory tree laid out like the mounted filesystem, ideally on a tmpfs.
+    Dir,
+    /// A tar archive of the directory tree.
+    Tar,
+    /// A single JSON document, with directories as objects and files as strings.
+    Json,
+}
+
+pub fn export(args: ExportArgs) -> anyhow::Result<()> {
+    if args.format == ExportFormat::Dir && args.output.is_none() {
+        anyhow::bail!("An output directory is required to export as a directory");
+    }
+    let mut backend = args.backend.build()?;
+    let was_unlocked = backend
+        .status()
+        .is_ok_and(|s| s.status == StatusKind::Unlocked);
+    if !was_unlocked {
+        let password = read_password(args.password_prompt)?;
+        if password.is_empty() {
+            anyhow::bail!("Got empty password");
+        }
+        backend.unlock(&password)?;
+        eprintln!("Unlocked");
+    }
+
+    let res = build_tree(backend.as_ref(), args.folders, args.layout)
+        .and_then(|fs| write_export(&fs, args.format, args.output.as_deref()));
+    if !was_unlocked {
+        // leave a vault that was unlocked beforehand as we found it
+        backend.lock();
+    }
+    res?;
+    eprintln!("Exported");
+    Ok(())
+}
+
+/// Build the same tree the filesystem would serve for the vault.
+fn build_tree(
+    backend: &dyn VaultBackend,
+    folders: Vec<String>,
+    layout: Layout,
+) -> anyhow::Result<MapFS> {
+    let (uid, gid) = current_ids();
+    let policy = Policy {
+        uid,
+        gid,
+        dir_mode: DIR_MODE,
+        file_mode: FILE_MODE,
+        secret_mode: FILE_MODE,
+        secret_fields: Vec::new(),
+        rules: Vec::new(),
+    };
+    let audit = AuditLogRef(Arc::new(Mutex::new(AuditLog::new(AuditSink::None))));
+    let approver = Approver::new(None, Duration::ZERO, Duration::ZERO);
+    let mut fs = MapFS::new(
+        policy,
+        folders,
+        audit,
+        approver,
+        None,
+        UriMatcher::load(Path::new(DEFAULT_PUBLIC_SUFFIX_LIST)),
+        layout,
+    );
+    fs.refresh(backend)?;
+    Ok(fs)
+}
+
+fn write_export(fs: &MapFS, format: ExportFormat, output: Option<&Path>) -> anyhow::Result<()> {
+    info!(?format, ?output, "Writing export");
+    match format {
+        ExportFormat::Dir => {
+            let Some(output) = output else {
+                anyhow::bail!("An output directory is required to export as a directory");
+            };
+            export_dir(fs, output)
+        }
+        ExportFormat::Tar => {
+            let mut builder = tar::Builder::new(open_output(output)?);
+            append_tar(fs, FUSE_ROOT_ID, Path::new(""), &mut builder)?;
+            builder.into_inner()?.flush()?;
+            Ok(())
+        }
+        ExportFormat::Json => {
+            let mut out = open_output(output)?;
+            serde_json::to_writer_pretty(&mut out, &to_json(fs, FUSE_ROOT_ID))?;
+            out.write_all(b"\n")?;
+            out.flush()?;
+            Ok(())
+        }
+    }
+}
+
+/// Open the file to write an export to, or stdout if no path is given.
+fn open_output(path: Option<&Path>) -> anyhow::Result<Box<dyn Write>> {
+    match path {
+        Some(path) => Ok(Box::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(FILE_MODE.into())
+                .open(path)?,
+        )),
+        None => Ok(Box::new(std::io::stdout().lock())),
+    }
+}
+
+fn export_dir(fs: &MapFS, output: &Path) -> anyhow::Result<()> {
+    if output.exists() && std::fs::read_dir(output)?.next().is_some() {
+        anyhow::bail!("Output directory {output:?} is not empty");
+    }
+    DirBuilder::new()
+        .recursive(true)
+        .mode(DIR_MODE.into())
+        .create(output)?;
+    write_dir(fs, FUSE_ROOT_ID, output)
+}
+
+fn write_dir(fs: &MapFS, ino: u64, path: &Path) -> anyhow::Result<()> {
+    let Some(FSEntry::Dir {
+        children, mtime, ..
+    }) = fs.entry(ino)
+    else {
+        return Ok(());
+    };
+    for (name, child) in children {
+        let child_path = path.join(name);
+        match fs.entry(*child) {
+            Some(FSEntry::Dir { .. }) => {
+                DirBuilder::new()
+                    .mode(DIR_MODE.into())
+                    .create(&child_path)?;
+                write_dir(fs, *child, &child_path)?;
+            }
+            Some(FSEntry::File { content, mtime, .. }) => {
+                debug!(?child_path, "Writing file");
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(FILE_MODE.into())
+                    .open(&child_path)?;
+                file.write_all(content.as_bytes())?;
+                file.set_modified(*mtime)?;
+            }
+            Some(FSEntry::Link { target, .. }) => symlink(target, &child_path)?,
+            None => {}
+        }
+    }
+    // after the children, as writing them updates it
+    File::open(path)?.set_modified(*mtime)?;
+    Ok(())
+}
+
+fn append_tar<W: Write>(
+    fs: &MapFS,
+    ino: u64,
+    path: &Path,
+    builder: &mut tar::Builder<W>,
+) -> anyhow::Result<()> {
+    let Some(FSEntry::Dir { children, .. }) = fs.entry(ino) else {
+        return Ok(());
+    };
+    for (name, child) in children {
+        let child_path = path.join(name);
+        let mut header = tar::Header::new_gnu();
+        match fs.entry(*child) {
+            Some(FSEntry::Dir { mtime, .. }) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(DIR_MODE.into());
+                header.set_mtime(unix_secs(*mtime));
+                header.set_size(0);
+                builder.append_data(&mut header, &child_path, std::io::empty())?;
+                append_tar(fs, *child, &child_path, builder)?;
+            }
+            Some(FSEntry::File { content, mtime, .. }) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(FILE_MODE.into());
+                header.set_mtime(unix_secs(*mtime));
+                header.set_size(content.len() as u64);
+                builder.append_data(&mut header, &child_path, content.as_bytes())?;
+            }
+            Some(FSEntry::Link { target, mtime, .. }) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_mtime(unix_secs(*mtime));
+                header.set_size(0);
+                builder.append_link(&mut header, &child_path, target)?;
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn to_json(fs: &MapFS, ino: u64) -> serde_json::Value {
+    match fs.entry(ino) {
+        Some(FSEntry::Dir { children, .. }) => {
+            let object: serde_json::Map<_, _> = children
+                .iter()
+                .map(|(name, child)| (name.clone(), to_json(fs, *child)))
+                .filter(|(_, value)| !value.is_null())
+                .collect();
+            // leave out directories of only links, like the views
+            if object.is_empty() && !children.is_empty() {
+                serde_json::Value::Null
+            } else {
+                object.into()
+            }
+        }
+        Some(FSEntry::File { content, .. }) => content.clone().into(),
+        // links only repeat what's elsewhere in the tree
+        Some(FSEntry::Link { .. }) | None => serde_json::Value::Null,
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::server::testing::ScriptedBackend;
+
+    fn tree(folders: &[&str]) -> MapFS {
+        let folders = folders.iter().map(|f| (*f).to_owned()).collect();
+        build_tree(&ScriptedBackend::unlocked(), folders, Layout::Nested).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bwfs-export-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn json_matches_tree() {
+        let mut json = to_json(&tree(&["prod"]), FUSE_ROOT_ID);
+        let database = json["prod"]["eu"]["database"].as_object_mut().unwrap();
+        assert_eq!(
+            database.remove("item.env").unwrap(),
+            "TYPE=Login\nUSERNAME=postgres\nPASSWORD=hunter2\nTOTP=JBSWY3DPEHPK3PXP\nFIELD_USER=postgres\nID=1c9e7d2b-6f4a-4b3c-8d8e-9f0a1b2c3d4e\n"
+        );
+        assert!(database.remove("item.json").is_some());
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "prod": {
+                    "eu": {
+                        "database": {
+                            "fields": { "user": "postgres" },
+                            "id": "1c9e7d2b-6f4a-4b3c-8d8e-9f0a1b2c3d4e",
+                            "password": "hunter2",
+                            "totp": "JBSWY3DPEHPK3PXP",
+                            "type": "Login",
+                            "username": "postgres",
+                        }
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn dir_export() {
+        let output = temp_path("dir");
+        let _ = std::fs::remove_dir_all(&output);
+        write_export(&tree(&[]), ExportFormat::Dir, Some(&output)).unwrap();
+
+        let password = output.join("web/github/password");
+        assert_eq!(
+            std::fs::read_to_string(&password).unwrap(),
+            "correct horse battery staple"
+        );
+        let mode = std::fs::metadata(&password).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            std::fs::read_to_string(output.join("web/github/uris/01")).unwrap(),
+            "https://github.com"
+        );
+
+        // refuses to mix with existing contents
+        assert!(write_export(&tree(&[]), ExportFormat::Dir, Some(&output)).is_err());
+        std::fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn tar_export() {
+        let output = temp_path("tar");
+        write_export(&tree(&[]), ExportFormat::Tar, Some(&output)).unwrap();
+
+        let mut archive = tar::Archive::new(File::open(&output).unwrap());
+        let mut files = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            files.insert(path, content);
+        }
+        assert_eq!(files["web/github/fields/pin"], "1234");
+        assert_eq!(files["No Folder/wifi/notes"], "guest network");
+        assert!(files.contains_key("prod/eu"));
+        std::fs::remove_file(output).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
 pub mod client;
+pub mod export;
 pub mod message;
 pub mod server;
+pub mod template;
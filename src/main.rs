@@ -1,10 +1,26 @@
+use bwfs::client::audit_tail;
+use bwfs::client::copy;
+use bwfs::client::exec;
+use bwfs::client::get;
+use bwfs::client::git_credential;
 use bwfs::client::lock;
+use bwfs::client::match_uri;
+use bwfs::client::pick;
 use bwfs::client::refresh;
+use bwfs::client::render;
 use bwfs::client::status;
 use bwfs::client::unlock;
+use bwfs::client::EnvVar;
+use bwfs::client::GitCredentialOperation;
+use bwfs::client::PickAction;
+use bwfs::client::DEFAULT_CLEAR_AFTER_S;
+use bwfs::export::export;
+use bwfs::export::ExportArgs;
 use bwfs::server::serve;
 use bwfs::server::ServeArgs;
 use clap::Subcommand;
+use std::path::PathBuf;
+use std::time::Duration;
 use tracing::info;
 
 use clap::Parser;
@@ -25,7 +41,7 @@ struct Opts {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Serve the filesystem.
-    Serve(ServeArgs),
+    Serve(Box<ServeArgs>),
 
     /// Unlock the vault.
     Unlock {
@@ -46,8 +62,136 @@ enum Command {
     /// Get the status of the filesystem.
     Status,
 
-    /// Refresh the contents of the filesystem from the vault.
+    /// Sync the vault with the server and refresh the contents of the filesystem from it.
     Refresh,
+
+    /// Get the value of a file in the vault through the running server.
+    Get {
+        /// Path relative to the mountpoint.
+        path: String,
+
+        /// Print the whole item the path belongs to as JSON.
+        #[clap(long)]
+        json: bool,
+
+        /// Copy the value to the clipboard instead of printing it, like `copy`.
+        #[clap(long, conflicts_with = "json")]
+        clipboard: bool,
+    },
+
+    /// Copy the value of a file in the vault to the clipboard.
+    ///
+    /// After the timeout the previous clipboard contents are restored, or the clipboard cleared,
+    /// if it still holds the value. Uses `wl-copy` on Wayland and `xclip` otherwise.
+    Copy {
+        /// Path relative to the mountpoint.
+        path: String,
+
+        /// Seconds to leave the value on the clipboard.
+        #[clap(long, default_value_t = DEFAULT_CLEAR_AFTER_S)]
+        clear_after_s: u64,
+    },
+
+    /// Pick a value from the vault with a fuzzy finder and print it.
+    ///
+    /// Unlocks first if the vault is locked. Only item paths, usernames and URIs are shown while
+    /// picking.
+    Pick {
+        /// Copy the chosen value to the clipboard instead, like `copy`.
+        #[clap(long, conflicts_with = "env")]
+        copy: bool,
+
+        /// Run a command with the chosen value set as this environment variable instead.
+        #[clap(long, value_name = "NAME", requires = "command")]
+        env: Option<String>,
+
+        /// Command to run with `--env` and its arguments.
+        #[clap(last = true)]
+        command: Vec<String>,
+
+        /// Custom password prompt script, used if the vault needs unlocking.
+        #[clap(long)]
+        password_prompt: Option<String>,
+    },
+
+    /// Run a command with values from the vault set as environment variables.
+    ///
+    /// Values are read through the running server, so the vault must be unlocked.
+    Exec {
+        /// Set a variable to the file at a path relative to the mountpoint, as `NAME=PATH`.
+        #[clap(long = "env", value_name = "NAME=PATH")]
+        env: Vec<EnvVar>,
+
+        /// TOML file of `NAME = "PATH"` pairs to set, overridden by `--env`.
+        #[clap(long)]
+        env_file: Vec<PathBuf>,
+
+        /// Command to run and its arguments.
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Render a template, replacing each `{{ secret "PATH" }}` with the file at that path.
+    ///
+    /// Paths are relative to the mountpoint and read through the running server.
+    Render {
+        /// Template to render.
+        template: PathBuf,
+
+        /// File to write to instead of stdout, replaced atomically.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Access controls for the output file, in octal form.
+        #[clap(short, long, default_value = "600")]
+        mode: String,
+
+        /// Keep running, rendering again when a referenced item changes after a refresh.
+        #[clap(long, requires = "output")]
+        watch: bool,
+
+        /// Seconds between checks for changes when watching.
+        #[clap(long, default_value = "30")]
+        interval_s: u64,
+    },
+
+    /// Print the paths of items with a login URI matching a URL, one per line.
+    ///
+    /// Each URI is matched by its own setting, by base domain if it has none.
+    Match {
+        /// URL to find logins for.
+        url: String,
+    },
+
+    /// Git credential helper answering with logins whose URIs match the repository.
+    ///
+    /// Set up with `git config credential.helper '!bwfs git-credential'`. Logins are read
+    /// through the running server, so the vault must be unlocked.
+    GitCredential {
+        #[clap(value_enum)]
+        operation: GitCredentialOperation,
+    },
+
+    /// Export the vault contents without mounting the filesystem.
+    ///
+    /// Uses the same layout as the mounted filesystem.
+    Export(Box<ExportArgs>),
+
+    /// Inspect the log of secret accesses.
+    Audit {
+        #[clap(subcommand)]
+        cmd: AuditCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AuditCommand {
+    /// Print the most recent accesses, only for the user running the server.
+    Tail {
+        /// Number of entries to print.
+        #[clap(short = 'n', long, default_value = "20")]
+        count: usize,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -57,7 +201,7 @@ fn main() -> anyhow::Result<()> {
     info!(?args, "Loaded args");
 
     match args.cmd {
-        Command::Serve(serve_args) => serve(args.socket, serve_args),
+        Command::Serve(serve_args) => serve(args.socket, *serve_args),
         Command::Unlock {
             no_refresh,
             password_prompt,
@@ -68,5 +212,52 @@ fn main() -> anyhow::Result<()> {
             std::process::exit(exit_code)
         }
         Command::Refresh => refresh(args.socket),
+        Command::Get {
+            path,
+            json,
+            clipboard,
+        } => get(args.socket, path, json, clipboard),
+        Command::Copy {
+            path,
+            clear_after_s,
+        } => copy(args.socket, path, Duration::from_secs(clear_after_s)),
+        Command::Pick {
+            copy,
+            env,
+            command,
+            password_prompt,
+        } => {
+            let action = match env {
+                Some(name) => PickAction::Exec { name, command },
+                None if copy => PickAction::Copy,
+                None => PickAction::Print,
+            };
+            pick(args.socket, password_prompt, action)
+        }
+        Command::Exec {
+            env,
+            env_file,
+            command,
+        } => exec(args.socket, env, env_file, command),
+        Command::Render {
+            template,
+            output,
+            mode,
+            watch,
+            interval_s,
+        } => render(
+            args.socket,
+            template,
+            output,
+            mode,
+            watch,
+            Duration::from_secs(interval_s),
+        ),
+        Command::Match { url } => match_uri(args.socket, url),
+        Command::GitCredential { operation } => git_credential(args.socket, operation),
+        Command::Export(export_args) => export(*export_args),
+        Command::Audit {
+            cmd: AuditCommand::Tail { count },
+        } => audit_tail(args.socket, count),
     }
 }
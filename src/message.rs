@@ -1,14 +1,84 @@
+use time::OffsetDateTime;
+
+use crate::server::audit::AuditEntry;
+use crate::server::mapfs::ItemSummary;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum Request {
-    Unlock { password: String },
+    Unlock {
+        password: String,
+    },
     Lock,
     Status,
     Refresh,
+    AuditTail {
+        count: usize,
+    },
+    /// Read the file at a path relative to the mountpoint.
+    Read {
+        path: String,
+    },
+    /// Get the whole item the file at a path relative to the mountpoint is in, as JSON.
+    GetItem {
+        path: String,
+    },
+    /// Copy the file at a path to the clipboard, taking it off again after a timeout.
+    Copy {
+        path: String,
+        clear_after_s: u64,
+        /// Variables locating the client's graphical session.
+        env: Vec<(String, String)>,
+    },
+    /// List the items in the filesystem, without their secrets.
+    List,
+    /// Get when the files at the given paths were last modified.
+    Modified {
+        paths: Vec<String>,
+    },
+    /// Find the items with a login URI matching a URL.
+    MatchUri {
+        uri: String,
+    },
+    /// Find login credentials for a URL, preferring those for `username` if given.
+    GitCredential {
+        url: String,
+        username: Option<String>,
+    },
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum Response {
-    Status { locked: bool },
+    Status {
+        locked: bool,
+        /// Set when serving an offline snapshot, to when it was taken.
+        #[serde(default, with = "time::serde::rfc3339::option")]
+        offline_since: Option<OffsetDateTime>,
+    },
+    Audit {
+        entries: Vec<AuditEntry>,
+    },
+    /// Contents of a file, never to be logged.
+    Value {
+        value: String,
+    },
+    Items {
+        items: Vec<ItemSummary>,
+    },
+    /// Paths of items relative to the mountpoint.
+    Paths {
+        paths: Vec<String>,
+    },
+    /// Login credentials, never to be logged.
+    Credential {
+        username: Option<String>,
+        password: String,
+    },
+    /// Modification times in the order requested, `None` for paths that couldn't be found.
+    Modified {
+        modified: Vec<Option<OffsetDateTime>>,
+    },
     Success,
-    Failure { reason: String },
+    Failure {
+        reason: String,
+    },
 }
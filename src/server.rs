@@ -1,29 +1,60 @@
 use std::{
     fs::remove_file,
     io::{BufRead, BufReader, ErrorKind, Write},
-    os::unix::net::{UnixListener, UnixStream},
+    os::unix::{
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
     sync::{mpsc, Arc, Mutex},
 };
 
-use clap::Args;
+use clap::{Args, ValueEnum};
 use fuser::MountOption;
 use std::time::Duration;
 use std::time::Instant;
 use sysinfo::{Groups, Pid, Users};
+use time::OffsetDateTime;
 use tracing::{debug, info, warn};
 
+use bwapi::BWApi;
 use bwclient::BWCLI;
+use bwserve::BWServe;
 use mapfs::MapFS;
+use offline::OfflineCache;
 
 use crate::{
     message::{Request, Response},
     server::bwclient::StatusKind,
 };
 
-use self::mapfs::MapFSRef;
+use self::approval::Approver;
+use self::audit::{AuditLog, AuditLogRef, AuditSink};
+use self::backend::VaultBackend;
+use self::clipboard::{Clipboard, ClipboardTimerRef};
+use self::layout::Layout;
+use self::mapfs::{Caller, MapFSRef};
+use self::policy::{FieldRule, FieldRuleSpec, Policy};
+use self::rules::AccessRules;
+use self::urimatch::UriMatcher;
 
+pub mod approval;
+pub mod audit;
+pub mod backend;
+pub mod bwapi;
 pub mod bwclient;
+pub mod bwcrypto;
+pub mod bwserve;
+pub mod clipboard;
+pub mod layout;
 pub mod mapfs;
+pub mod offline;
+pub mod policy;
+pub mod rules;
+pub mod sshagent;
+#[cfg(test)]
+pub mod testing;
+pub mod urimatch;
 
 #[derive(Debug, Args)]
 pub struct ServeArgs {
@@ -35,9 +66,16 @@ pub struct ServeArgs {
     #[clap(long)]
     no_auto_unmount: bool,
 
-    /// Path to the bw binary.
-    #[clap(long, default_value = "bw")]
-    bw_bin: String,
+    #[clap(flatten)]
+    backend: BackendArgs,
+
+    /// Keep an encrypted snapshot of the vault from the last refresh at the given path.
+    ///
+    /// If the vault can't be unlocked, e.g. when offline, the snapshot is served instead after
+    /// unlocking it with the master password. It is keyed with the account's KDF settings, looked
+    /// up from the server when unlocking, or Bitwarden's default if they can't be.
+    #[clap(long)]
+    offline_cache: Option<PathBuf>,
 
     /// Filter results to those in the folders listed.
     #[clap(long, value_delimiter = ',')]
@@ -52,33 +90,164 @@ pub struct ServeArgs {
     group: Option<String>,
 
     /// File access controls, in octal form.
-    #[clap(short, long, default_value = "440")]
-    mode: String,
+    #[clap(short, long, default_value = "440", value_parser = policy::parse_mode)]
+    mode: u16,
+
+    /// Directory access controls, in octal form.
+    #[clap(long, default_value = "550", value_parser = policy::parse_mode)]
+    dir_mode: u16,
+
+    /// Access controls for files holding secret fields, in octal form.
+    #[clap(long, default_value = "400", value_parser = policy::parse_mode)]
+    secret_mode: u16,
+
+    /// Fields considered secret, relative to the item directory.
+    ///
+    /// A field also covers anything nested beneath it, e.g. `fields` covers all custom fields.
+    /// Hidden custom fields are always secret.
+    #[clap(long, value_delimiter = ',', default_value = policy::DEFAULT_SECRET_FIELDS)]
+    secret_fields: Vec<String>,
+
+    /// Override the access controls for a field, as `FIELD=MODE[:USER[:GROUP]]`.
+    ///
+    /// For example `username=440::services` lets the `services` group read usernames.
+    /// Can be given multiple times, later rules take precedence.
+    #[clap(long)]
+    field_policy: Vec<FieldRuleSpec>,
 
     /// Lock the filesystem after the given number of seconds since unlock.
     ///
     /// Set to 0 to disable auto lock.
     #[clap(long, default_value = "300")]
     lock_after_s: u64,
+
+    /// Append a record of every secret access to the given file.
+    #[clap(long, conflicts_with = "audit_journal")]
+    audit_log: Option<PathBuf>,
+
+    /// Send a record of every secret access to the systemd journal.
+    #[clap(long)]
+    audit_journal: bool,
+
+    /// Custom approval prompt script for items that require master password reprompt.
+    ///
+    /// Access is approved if the script exits successfully. The requesting process is described
+    /// by the `BWFS_ITEM_ID`, `BWFS_ITEM_NAME`, `BWFS_FIELD`, `BWFS_UID`, `BWFS_PID` and `BWFS_EXE`
+    /// environment variables. Without a script such items cannot be read.
+    #[clap(long)]
+    approval_prompt: Option<String>,
+
+    /// Number of seconds an approval lasts for the same item, user and executable.
+    #[clap(long, default_value = "60")]
+    approval_window_s: u64,
+
+    /// Number of seconds to wait for an approval before denying access.
+    #[clap(long, default_value = "60")]
+    approval_timeout_s: u64,
+
+    /// JSON file of rules restricting which executables may read which items.
+    ///
+    /// Each rule is an object like `{"path": "ssh/**", "exe": ["/usr/bin/ssh"]}`, only processes
+    /// running, or descended from, a listed executable can read matching items. Paths may also
+    /// name fields, like `prod/*/password`, and are those of the nested layout whatever the
+    /// layout. The file is reloaded when it changes.
+    #[clap(long)]
+    access_rules: Option<PathBuf>,
+
+    /// Serve the SSH keys in the vault over an ssh-agent socket at the given path.
+    ///
+    /// Point `SSH_AUTH_SOCK` at it to use them. Signing needs the vault to be unlocked and read
+    /// access to the key's `private_key` file, with the same approval and audit as reading it.
+    #[clap(long)]
+    ssh_agent: Option<PathBuf>,
+
+    /// Public suffix list used to find the base domain when matching login URIs.
+    #[clap(long, default_value = urimatch::DEFAULT_PUBLIC_SUFFIX_LIST)]
+    public_suffix_list: PathBuf,
+
+    /// How to lay out each item below its folder.
+    #[clap(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+}
+
+/// Options choosing and configuring the vault backend.
+#[derive(Debug, Args)]
+pub struct BackendArgs {
+    /// Path to the bw binary.
+    #[clap(long, default_value = "bw")]
+    bw_bin: String,
+
+    /// How to talk to the vault.
+    #[clap(long, value_enum, default_value_t = Backend::Cli)]
+    backend: Backend,
+
+    /// Server to log in to with the api backend.
+    #[clap(long, default_value = bwapi::DEFAULT_SERVER_URL)]
+    server_url: String,
+
+    /// Account email to log in with, required for the api backend.
+    #[clap(long, required_if_eq("backend", "api"))]
+    email: Option<String>,
+}
+
+impl BackendArgs {
+    /// Create the configured backend, starting any helper process it needs.
+    pub fn build(&self) -> anyhow::Result<Box<dyn VaultBackend>> {
+        Ok(match self.backend {
+            Backend::Cli => Box::new(BWCLI::new(self.bw_bin.clone())),
+            Backend::Serve => Box::new(BWServe::start(&self.bw_bin)?),
+            Backend::Api => Box::new(BWApi::new(
+                &self.server_url,
+                self.email.clone().unwrap_or_default(),
+            )),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Run a `bw` subprocess for every operation.
+    Cli,
+    /// Keep a single `bw serve` process running and use its HTTP API.
+    ///
+    /// Much faster. The API listens on a unix socket in a directory only this user can enter,
+    /// which needs a `bw` that accepts `--hostname unix:<path>`.
+    Serve,
+    /// Log in to the Bitwarden API directly and decrypt the vault in process, without `bw`.
+    ///
+    /// Accounts using two-step login are not supported.
+    Api,
 }
 
 pub fn serve(socket: String, args: ServeArgs) -> anyhow::Result<()> {
-    let (fs, cli) = bw_init(&args);
+    let audit_sink = if let Some(path) = &args.audit_log {
+        AuditSink::file(path)?
+    } else if args.audit_journal {
+        AuditSink::journal()?
+    } else {
+        AuditSink::None
+    };
+    let audit = AuditLogRef(Arc::new(Mutex::new(AuditLog::new(audit_sink))));
+    let (fs, backend) = bw_init(&args, audit.clone())?;
     let fs_ref = MapFSRef(Arc::new(Mutex::new(fs)));
-    let cli_ref = Arc::new(Mutex::new(cli));
+    let backend_ref = Arc::new(Mutex::new(backend));
     info!(args.mountpoint, "Configuring mount");
     let mut mount_options = Vec::new();
     mount_options.push(MountOption::RO);
+    // have the kernel check permissions too, on top of our own checks in the filesystem
+    mount_options.push(MountOption::DefaultPermissions);
     if !args.no_auto_unmount {
         mount_options.push(MountOption::AutoUnmount);
         mount_options.push(MountOption::AllowOther);
     }
 
     let (sender, receiver) = mpsc::channel::<()>();
+    let clipboard = ClipboardTimerRef::default();
 
     if args.lock_after_s > 0 {
         let fs = fs_ref.clone();
-        let cli = Arc::clone(&cli_ref);
+        let backend = Arc::clone(&backend_ref);
+        let clipboard = clipboard.clone();
         std::thread::Builder::new()
             .name("lock-after".to_owned())
             .spawn(move || {
@@ -98,89 +267,148 @@ pub fn serve(socket: String, args: ServeArgs) -> anyhow::Result<()> {
                         }
                     }
 
-                    let unlocked = cli
+                    let unlocked = backend
                         .lock()
                         .unwrap()
                         .status()
-                        .map_or(false, |s| s.status == StatusKind::Unlocked);
+                        .is_ok_and(|s| s.status == StatusKind::Unlocked);
                     if unlocked {
                         debug!(
                             args.lock_after_s,
-                            "CLI unlocked, waiting for lock after duration"
+                            "Vault unlocked, waiting for lock after duration"
                         );
                         std::thread::sleep(Duration::from_secs(args.lock_after_s));
                         debug!("Lock after duration passed, clearing and locking");
                         fs.clear();
-                        cli.lock().unwrap().lock();
+                        backend.lock().unwrap().lock();
+                        clipboard.restore(None);
                     }
                 }
             })
             .unwrap();
     }
 
+    if let Some(path) = args.ssh_agent {
+        sshagent::spawn(path, Arc::clone(&backend_ref), fs_ref.clone())?;
+    }
+
     println!("Mount configured at {:?}", args.mountpoint);
     let _mount = fuser::spawn_mount2(fs_ref.clone(), args.mountpoint, &mount_options).unwrap();
-    serve_commands(socket.clone(), &cli_ref, fs_ref, &sender);
+    serve_commands(
+        socket.clone(),
+        &backend_ref,
+        fs_ref,
+        &audit,
+        &sender,
+        &clipboard,
+    );
     remove_file(socket)?;
     Ok(())
 }
 
-fn bw_init(args: &ServeArgs) -> (MapFS, BWCLI) {
-    let uid = if let Some(user) = &args.user {
-        let users = Users::new_with_refreshed_list();
-        if let Some(user) = users.iter().find(|u| u.name() == user).map(|u| u.id()) {
-            **user
-        } else {
-            panic!("Couldn't find user {user}");
-        }
-    } else {
-        let s = sysinfo::System::new_all();
-        let self_pid = std::process::id();
-        *s.process(Pid::from_u32(self_pid))
-            .unwrap()
-            .user_id()
-            .unwrap()
-            .clone()
-    };
-    let gid = if let Some(group) = &args.group {
-        let groups = Groups::new_with_refreshed_list();
-        if let Some(group) = groups.iter().find(|g| g.name() == group).map(|g| g.id()) {
-            **group
-        } else {
-            panic!("Couldn't find group {group}");
-        }
-    } else {
-        let s = sysinfo::System::new_all();
-        let self_pid = std::process::id();
-        *s.process(Pid::from_u32(self_pid))
-            .unwrap()
-            .group_id()
-            .unwrap()
-    };
-    let mode = u16::from_str_radix(&args.mode, 8).unwrap();
-    debug!(
+fn bw_init(args: &ServeArgs, audit: AuditLogRef) -> anyhow::Result<(MapFS, Box<dyn VaultBackend>)> {
+    let (self_uid, self_gid) = current_ids();
+    let uid = args.user.as_deref().map_or(self_uid, find_user);
+    let gid = args.group.as_deref().map_or(self_gid, find_group);
+    let rules = args
+        .field_policy
+        .iter()
+        .map(|spec| FieldRule {
+            field: spec.field.clone(),
+            mode: spec.mode,
+            uid: spec.user.as_deref().map(find_user),
+            gid: spec.group.as_deref().map(find_group),
+        })
+        .collect();
+    let policy = Policy {
         uid,
-        gid, mode, "Initialised bitwarden client and filesystem"
+        gid,
+        dir_mode: args.dir_mode,
+        file_mode: args.mode,
+        secret_mode: args.secret_mode,
+        secret_fields: args.secret_fields.clone(),
+        rules,
+    };
+    debug!(?policy, "Initialised bitwarden client and filesystem");
+
+    let approver = Approver::new(
+        args.approval_prompt.clone(),
+        Duration::from_secs(args.approval_window_s),
+        Duration::from_secs(args.approval_timeout_s),
+    );
+    let rules = args
+        .access_rules
+        .clone()
+        .map(AccessRules::load)
+        .transpose()?;
+    let matcher = UriMatcher::load(&args.public_suffix_list);
+    let fs = MapFS::new(
+        policy,
+        args.folders.clone(),
+        audit,
+        approver,
+        rules,
+        matcher,
+        args.layout,
     );
 
-    let fs = MapFS::new(uid, gid, mode, args.folders.clone());
+    let mut backend = args.backend.build()?;
+    if let Some(path) = &args.offline_cache {
+        backend = Box::new(OfflineCache::new(backend, path.clone()));
+    }
+    Ok((fs, backend))
+}
 
-    let cli = BWCLI::new(args.bw_bin.clone());
-    (fs, cli)
+/// User and group ids of this process.
+pub fn current_ids() -> (u32, u32) {
+    let s = sysinfo::System::new_all();
+    let process = s.process(Pid::from_u32(std::process::id())).unwrap();
+    (**process.user_id().unwrap(), *process.group_id().unwrap())
+}
+
+fn find_user(user: &str) -> u32 {
+    let users = Users::new_with_refreshed_list();
+    if let Some(user) = users.iter().find(|u| u.name() == user).map(|u| u.id()) {
+        **user
+    } else {
+        panic!("Couldn't find user {user}");
+    }
+}
+
+fn find_group(group: &str) -> u32 {
+    let groups = Groups::new_with_refreshed_list();
+    if let Some(group) = groups.iter().find(|g| g.name() == group).map(|g| g.id()) {
+        **group
+    } else {
+        panic!("Couldn't find group {group}");
+    }
 }
 
 fn serve_commands(
     socket: String,
-    cli: &Arc<Mutex<BWCLI>>,
+    backend: &Arc<Mutex<Box<dyn VaultBackend>>>,
     fs: MapFSRef,
+    audit: &AuditLogRef,
     unlock_notify: &mpsc::Sender<()>,
+    clipboard: &ClipboardTimerRef,
 ) {
     info!(socket, "Starting listening");
     let listener = bind_socket_or_remove(socket).unwrap();
     loop {
         let (stream, _addr) = listener.accept().unwrap();
         debug!("Accepted connection");
-        handle_stream(stream, cli, fs.clone(), unlock_notify);
+        // on its own thread, so a request waiting on an approval doesn't hold up e.g. locking
+        let backend = Arc::clone(backend);
+        let fs = fs.clone();
+        let audit = audit.clone();
+        let unlock_notify = unlock_notify.clone();
+        let clipboard = clipboard.clone();
+        let spawned = std::thread::Builder::new()
+            .name("connection".to_owned())
+            .spawn(move || handle_stream(stream, &backend, fs, &audit, &unlock_notify, &clipboard));
+        if let Err(error) = spawned {
+            warn!(%error, "Failed to spawn connection thread");
+        }
     }
 }
 
@@ -216,9 +444,11 @@ fn bind_socket_or_remove(socket: String) -> anyhow::Result<UnixListener> {
 
 fn handle_stream(
     stream: UnixStream,
-    cli: &Arc<Mutex<BWCLI>>,
+    backend: &Arc<Mutex<Box<dyn VaultBackend>>>,
     fs: MapFSRef,
+    audit: &AuditLogRef,
     unlock_notify: &mpsc::Sender<()>,
+    clipboard: &ClipboardTimerRef,
 ) {
     let mut input = Vec::new();
     let mut reader = BufReader::new(stream);
@@ -229,8 +459,27 @@ fn handle_stream(
     match serde_json::from_slice::<Request>(&input) {
         Ok(request) => {
             debug!("Parsed request");
-            let res = handle_request(request, cli, fs, unlock_notify);
-            debug!(?res, "Sending response");
+            let caller = match peer_caller(&stream) {
+                Ok(caller) => Some(caller),
+                Err(error) => {
+                    warn!(%error, "Failed to get client credentials");
+                    None
+                }
+            };
+            let res = handle_request(
+                request,
+                caller,
+                backend,
+                fs,
+                audit,
+                unlock_notify,
+                clipboard,
+            );
+            if let Response::Value { .. } | Response::Credential { .. } = res {
+                debug!("Sending value");
+            } else {
+                debug!(?res, "Sending response");
+            }
             let json_res = serde_json::to_vec(&res).unwrap();
             stream.write_all(&json_res).unwrap();
         }
@@ -240,16 +489,86 @@ fn handle_stream(
     }
 }
 
+/// Identify the process on the other end of a control socket connection.
+fn peer_caller(stream: &UnixStream) -> std::io::Result<Caller> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len are valid for writes and len holds the size of cred
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            std::ptr::addr_of_mut!(cred).cast(),
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(Caller {
+        uid: cred.uid,
+        gid: cred.gid,
+        pid: cred.pid as u32,
+    })
+}
+
+/// Check a request reading the filesystem can be served, returning the caller to read as.
+fn check_read(
+    caller: Option<Caller>,
+    backend: &Arc<Mutex<Box<dyn VaultBackend>>>,
+) -> Result<Caller, Response> {
+    let unlocked = backend
+        .lock()
+        .unwrap()
+        .status()
+        .is_ok_and(|s| s.status == StatusKind::Unlocked);
+    if !unlocked {
+        Err(Response::Failure {
+            reason: "Vault is locked".to_owned(),
+        })
+    } else {
+        caller.ok_or_else(|| Response::Failure {
+            reason: "Couldn't identify the requesting process".to_owned(),
+        })
+    }
+}
+
+/// Check a request is from the user running the server, for those revealing its state.
+fn check_owner(caller: Option<Caller>) -> Result<(), Response> {
+    // SAFETY: getuid has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    match caller {
+        Some(caller) if caller.uid == uid => Ok(()),
+        Some(caller) => {
+            warn!(caller.uid, caller.pid, "Refused request from another user");
+            Err(Response::Failure {
+                reason: "Only the user running the server can do this".to_owned(),
+            })
+        }
+        None => Err(Response::Failure {
+            reason: "Couldn't identify the requesting process".to_owned(),
+        }),
+    }
+}
+
 fn handle_request(
     request: Request,
-    cli: &Arc<Mutex<BWCLI>>,
+    caller: Option<Caller>,
+    backend: &Arc<Mutex<Box<dyn VaultBackend>>>,
     fs: MapFSRef,
+    audit: &AuditLogRef,
     unlock_notify: &mpsc::Sender<()>,
+    clipboard: &ClipboardTimerRef,
 ) -> Response {
     match request {
         Request::Unlock { password } => {
             let start = Instant::now();
-            let res = match cli.lock().unwrap().unlock(&password) {
+            let res = match backend.lock().unwrap().unlock(&password) {
                 Ok(()) => {
                     let _ = unlock_notify.send(());
                     Response::Success
@@ -265,16 +584,19 @@ fn handle_request(
         Request::Lock => {
             let start = Instant::now();
             fs.clear();
-            cli.lock().unwrap().lock();
+            backend.lock().unwrap().lock();
+            clipboard.restore(None);
             let time = start.elapsed();
             debug!(?time, "Lock complete");
             Response::Success
         }
         Request::Status => {
             let start = Instant::now();
-            let res = match cli.lock().unwrap().status() {
+            let backend = backend.lock().unwrap();
+            let res = match backend.status() {
                 Ok(s) => Response::Status {
                     locked: s.status == StatusKind::Locked,
+                    offline_since: backend.offline_since(),
                 },
                 Err(e) => Response::Failure {
                     reason: e.to_string(),
@@ -286,7 +608,17 @@ fn handle_request(
         }
         Request::Refresh => {
             let start = Instant::now();
-            let res = match fs.refresh(&cli.lock().unwrap()) {
+            let mut backend = backend.lock().unwrap();
+            // pull changes from the server, which the backends otherwise only do on unlock
+            if let Err(e) = backend.sync() {
+                if backend.offline_since().is_none() {
+                    return Response::Failure {
+                        reason: format!("Failed to sync: {e}"),
+                    };
+                }
+                warn!(error = %e, "Serving offline snapshot, refreshing without syncing");
+            }
+            let res = match fs.refresh(backend.as_ref()) {
                 Ok(()) => Response::Success,
                 Err(e) => Response::Failure {
                     reason: e.to_string(),
@@ -296,5 +628,186 @@ fn handle_request(
             debug!(?time, "Refresh complete");
             res
         }
+        Request::AuditTail { count } => match check_owner(caller) {
+            Ok(()) => Response::Audit {
+                entries: audit.tail(count),
+            },
+            Err(res) => res,
+        },
+        Request::Read { path } => {
+            let caller = match check_read(caller, backend) {
+                Ok(caller) => caller,
+                Err(res) => return res,
+            };
+            match fs.read_path(&caller, &path) {
+                Ok(value) => Response::Value { value },
+                Err(errno) => read_failure(&path, errno),
+            }
+        }
+        Request::GetItem { path } => {
+            let caller = match check_read(caller, backend) {
+                Ok(caller) => caller,
+                Err(res) => return res,
+            };
+            match fs.read_item(&caller, &path) {
+                Ok(item) => Response::Value {
+                    value: serde_json::to_string_pretty(&item).unwrap(),
+                },
+                Err(errno) => read_failure(&path, errno),
+            }
+        }
+        Request::Copy {
+            path,
+            clear_after_s,
+            env,
+        } => {
+            let caller = match check_read(caller, backend) {
+                Ok(caller) => caller,
+                Err(res) => return res,
+            };
+            let value = match fs.read_path(&caller, &path) {
+                Ok(value) => value,
+                Err(errno) => return read_failure(&path, errno),
+            };
+            let copied = Clipboard::new(env, caller.uid)
+                .and_then(|c| clipboard.copy(c, value, Duration::from_secs(clear_after_s)));
+            match copied {
+                Ok(()) => Response::Success,
+                Err(e) => Response::Failure {
+                    reason: format!("{e:#}"),
+                },
+            }
+        }
+        Request::List => match check_read(caller, backend) {
+            Ok(caller) => Response::Items {
+                items: fs.list_items(&caller),
+            },
+            Err(res) => res,
+        },
+        Request::Modified { paths } => {
+            let caller = match check_read(caller, backend) {
+                Ok(caller) => caller,
+                Err(res) => return res,
+            };
+            Response::Modified {
+                modified: paths
+                    .iter()
+                    .map(|path| fs.modified(&caller, path).ok().map(OffsetDateTime::from))
+                    .collect(),
+            }
+        }
+        Request::MatchUri { uri } => match check_read(caller, backend) {
+            Ok(caller) => {
+                let mut paths = fs.match_uri(&caller, &uri);
+                paths.sort();
+                Response::Paths { paths }
+            }
+            Err(res) => res,
+        },
+        Request::GitCredential { url, username } => {
+            let caller = match check_read(caller, backend) {
+                Ok(caller) => caller,
+                Err(res) => return res,
+            };
+            git_credential(&fs, &caller, &url, username.as_deref())
+        }
+    }
+}
+
+/// Failure to read `path` from the filesystem with `errno`.
+fn read_failure(path: &str, errno: i32) -> Response {
+    Response::Failure {
+        reason: format!("{path}: {}", std::io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Credentials from the first matching login `caller` can read, only those for `username` if given.
+fn git_credential(fs: &MapFSRef, caller: &Caller, url: &str, username: Option<&str>) -> Response {
+    let mut paths = fs.match_uri(caller, url);
+    // stay predictable when several logins match
+    paths.sort();
+    debug!(
+        url,
+        matches = paths.len(),
+        "Matched logins for git credential"
+    );
+    for path in paths {
+        // read rather than taken from the item summaries, which leave out secret usernames
+        let login_username = fs.read_field(caller, &path, "username").ok();
+        if username.is_some() && login_username.as_deref() != username {
+            continue;
+        }
+        if let Ok(password) = fs.read_field(caller, &path, "password") {
+            info!(path, "Providing git credential");
+            return Response::Credential {
+                username: login_username,
+                password,
+            };
+        }
+    }
+    Response::Failure {
+        reason: format!("No readable login matches {url}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::approval::Approver;
+    use crate::server::layout::Layout;
+    use crate::server::policy::Policy;
+    use crate::server::testing::ScriptedBackend;
+    use crate::server::urimatch::UriMatcher;
+
+    #[test]
+    fn git_credential_with_secret_username() {
+        let policy = Policy {
+            uid: 1000,
+            gid: 1000,
+            dir_mode: 0o550,
+            file_mode: 0o440,
+            secret_mode: 0o400,
+            secret_fields: vec!["password".to_owned(), "username".to_owned()],
+            rules: Vec::new(),
+        };
+        let mut fs = MapFS::new(
+            policy,
+            Vec::new(),
+            AuditLogRef(Arc::new(Mutex::new(AuditLog::new(AuditSink::None)))),
+            Approver::new(None, Duration::ZERO, Duration::ZERO),
+            None,
+            UriMatcher::default(),
+            Layout::default(),
+        );
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let fs = MapFSRef(Arc::new(Mutex::new(fs)));
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        let url = "https://github.com/octocat/repo.git";
+
+        let Response::Credential { username, password } =
+            git_credential(&fs, &owner, url, Some("octocat"))
+        else {
+            panic!("expected a credential");
+        };
+        assert_eq!(username.as_deref(), Some("octocat"));
+        assert_eq!(password, "correct horse battery staple");
+
+        assert!(matches!(
+            git_credential(&fs, &owner, url, Some("someone")),
+            Response::Failure { .. }
+        ));
+        let other = Caller {
+            uid: 2000,
+            gid: 2000,
+            ..owner
+        };
+        assert!(matches!(
+            git_credential(&fs, &other, url, Some("octocat")),
+            Response::Failure { .. }
+        ));
     }
 }
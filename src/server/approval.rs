@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::audit::process_exe;
+
+/// How often to check whether an approval prompt has finished.
+const PROMPT_POLL: Duration = Duration::from_millis(50);
+
+/// Who is approved to access an item: the item, user and executable.
+type ApprovalKey = (Uuid, u32, Option<String>);
+
+/// Asks the user to approve access to items marked for master password reprompt.
+#[derive(Debug)]
+pub struct Approver {
+    /// Script to run for approval, access is approved if it exits successfully.
+    prompt: Option<String>,
+    /// How long an approval lasts for.
+    window: Duration,
+    /// How long to wait for the user before denying access.
+    timeout: Duration,
+    approved: BTreeMap<ApprovalKey, Instant>,
+    /// An approval just given, which holds for the access it was asked for even outside the
+    /// window.
+    fresh: Option<ApprovalKey>,
+}
+
+/// Outcome of checking for an approval.
+#[derive(Debug)]
+pub enum Approval {
+    Approved,
+    Denied,
+    /// The user needs asking, by running the prompt.
+    Prompt(Box<ApprovalPrompt>),
+}
+
+/// A prompt asking the user to approve an access, run without holding up other requests.
+#[derive(Debug)]
+pub struct ApprovalPrompt {
+    key: ApprovalKey,
+    command: Command,
+    timeout: Duration,
+}
+
+impl Approver {
+    pub fn new(prompt: Option<String>, window: Duration, timeout: Duration) -> Self {
+        Self {
+            prompt,
+            window,
+            timeout,
+            approved: BTreeMap::new(),
+            fresh: None,
+        }
+    }
+
+    /// Whether the process `pid`, run by `uid`, may access `field` of the given item.
+    ///
+    /// Approvals are remembered per item, user and executable for the approval window.
+    pub fn check(&mut self, item: Uuid, name: &str, field: &str, uid: u32, pid: u32) -> Approval {
+        let exe = process_exe(pid);
+        let key = (item, uid, exe.clone());
+        if self.fresh.as_ref() == Some(&key) {
+            self.fresh = None;
+            return Approval::Approved;
+        }
+        if let Some(at) = self.approved.get(&key) {
+            if at.elapsed() < self.window {
+                debug!(%item, uid, ?exe, "Access already approved");
+                return Approval::Approved;
+            }
+        }
+        let Some(prompt) = &self.prompt else {
+            warn!(%item, "Item requires approval but no approval prompt is configured");
+            return Approval::Denied;
+        };
+        let mut command = Command::new(prompt);
+        command
+            .env("BWFS_ITEM_ID", item.to_string())
+            .env("BWFS_ITEM_NAME", name)
+            .env("BWFS_FIELD", field)
+            .env("BWFS_UID", uid.to_string())
+            .env("BWFS_PID", pid.to_string())
+            .env("BWFS_EXE", exe.as_deref().unwrap_or_default())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null());
+        Approval::Prompt(Box::new(ApprovalPrompt {
+            key,
+            command,
+            timeout: self.timeout,
+        }))
+    }
+
+    /// Remember the approval given through `prompt`.
+    pub fn remember(&mut self, prompt: &ApprovalPrompt) {
+        self.approved.insert(prompt.key.clone(), Instant::now());
+        self.fresh = Some(prompt.key.clone());
+    }
+
+    /// Forget the approvals for items other than those to `keep`.
+    pub fn retain(&mut self, keep: impl Fn(&Uuid) -> bool) {
+        self.approved.retain(|(item, _, _), _| keep(item));
+        self.fresh = None;
+    }
+
+    /// Forget all approvals.
+    pub fn clear(&mut self) {
+        self.approved.clear();
+        self.fresh = None;
+    }
+}
+
+impl ApprovalPrompt {
+    /// Ask the user, denying access if they don't answer within the timeout.
+    pub fn run(&mut self) -> bool {
+        debug!(command = ?self.command, "Asking for approval with custom script");
+        let mut child = match self.command.spawn() {
+            Ok(child) => child,
+            Err(error) => {
+                warn!(%error, "Failed to run approval prompt");
+                return false;
+            }
+        };
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => return status.success(),
+                Ok(None) if Instant::now() < deadline => std::thread::sleep(PROMPT_POLL),
+                Ok(None) => {
+                    warn!(timeout = ?self.timeout, "Approval prompt timed out, denying access");
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return false;
+                }
+                Err(error) => {
+                    warn!(%error, "Failed to wait for approval prompt");
+                    let _ = child.kill();
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ITEM: Uuid = Uuid::from_u128(1);
+
+    fn check(approver: &mut Approver) -> Approval {
+        approver.check(ITEM, "visa", "card/code", 1000, std::process::id())
+    }
+
+    #[test]
+    fn prompts_until_approved() {
+        let mut approver = Approver::new(
+            Some("true".to_owned()),
+            Duration::from_secs(60),
+            Duration::from_secs(10),
+        );
+        let Approval::Prompt(mut prompt) = check(&mut approver) else {
+            panic!("expected a prompt");
+        };
+        assert!(prompt.run());
+        let Approval::Prompt(prompt) = check(&mut approver) else {
+            panic!("not approved until remembered");
+        };
+        approver.remember(&prompt);
+        assert!(matches!(check(&mut approver), Approval::Approved));
+        assert!(matches!(check(&mut approver), Approval::Approved));
+
+        approver.retain(|item| *item != ITEM);
+        assert!(matches!(check(&mut approver), Approval::Prompt(_)));
+    }
+
+    #[test]
+    fn fresh_approval_outside_window() {
+        let mut approver = Approver::new(Some("true".to_owned()), Duration::ZERO, Duration::ZERO);
+        let Approval::Prompt(prompt) = check(&mut approver) else {
+            panic!("expected a prompt");
+        };
+        approver.remember(&prompt);
+        // good for the access it was given for, but only that one
+        assert!(matches!(check(&mut approver), Approval::Approved));
+        assert!(matches!(check(&mut approver), Approval::Prompt(_)));
+    }
+
+    #[test]
+    fn denied_without_answer() {
+        let mut approver = Approver::new(None, Duration::from_secs(60), Duration::from_secs(10));
+        assert!(matches!(check(&mut approver), Approval::Denied));
+
+        let mut approver = Approver::new(
+            Some("false".to_owned()),
+            Duration::from_secs(60),
+            Duration::from_secs(10),
+        );
+        let Approval::Prompt(mut prompt) = check(&mut approver) else {
+            panic!("expected a prompt");
+        };
+        assert!(!prompt.run());
+
+        let mut approver = Approver::new(
+            Some("sleep".to_owned()),
+            Duration::from_secs(60),
+            Duration::from_millis(100),
+        );
+        let Approval::Prompt(mut prompt) = check(&mut approver) else {
+            panic!("expected a prompt");
+        };
+        prompt.command.arg("10");
+        let start = Instant::now();
+        assert!(!prompt.run());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
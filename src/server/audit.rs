@@ -0,0 +1,276 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::{fs::OpenOptionsExt, net::UnixDatagram},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Number of entries kept in memory for `bwfs audit tail`.
+const RECENT_ENTRIES: usize = 1000;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditOperation {
+    Open,
+    Read,
+    Denied,
+}
+
+impl Display for AuditOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            AuditOperation::Open => "open",
+            AuditOperation::Read => "read",
+            AuditOperation::Denied => "denied",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A record of a process accessing, or being denied access to, a secret.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub operation: AuditOperation,
+    pub item_id: Uuid,
+    pub field: String,
+    pub uid: u32,
+    pub pid: u32,
+    pub exe: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        operation: AuditOperation,
+        item_id: Uuid,
+        field: String,
+        uid: u32,
+        pid: u32,
+    ) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            operation,
+            item_id,
+            field,
+            uid,
+            pid,
+            exe: process_exe(pid),
+        }
+    }
+}
+
+impl Display for AuditEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let timestamp = self
+            .timestamp
+            .format(&Rfc3339)
+            .map_err(|_| std::fmt::Error)?;
+        write!(
+            f,
+            "{} {} item={} field={} uid={} pid={} exe={}",
+            timestamp,
+            self.operation,
+            self.item_id,
+            self.field,
+            self.uid,
+            self.pid,
+            self.exe.as_deref().unwrap_or("?"),
+        )
+    }
+}
+
+/// The executable path of a process, if it can be read.
+pub fn process_exe(pid: u32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{pid}/exe"))
+        .ok()
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+/// Where audit entries are persisted, on top of the in-memory recent entries.
+#[derive(Debug)]
+pub enum AuditSink {
+    None,
+    File(File),
+    Journal(UnixDatagram),
+}
+
+impl AuditSink {
+    /// Open a file for appending entries, creating it readable only by the owner.
+    pub fn file(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(Self::File(file))
+    }
+
+    /// Send entries to the systemd journal.
+    pub fn journal() -> anyhow::Result<Self> {
+        Self::journal_at(Path::new(JOURNAL_SOCKET))
+    }
+
+    fn journal_at(path: &Path) -> anyhow::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self::Journal(socket))
+    }
+
+    fn write(&mut self, entry: &AuditEntry) -> anyhow::Result<()> {
+        match self {
+            AuditSink::None => {}
+            AuditSink::File(file) => {
+                let mut line = serde_json::to_vec(entry)?;
+                line.push(b'\n');
+                file.write_all(&line)?;
+            }
+            AuditSink::Journal(socket) => {
+                // native journal protocol, values must not contain newlines
+                let message = format!(
+                    "MESSAGE={}\nSYSLOG_IDENTIFIER=bwfs\nBWFS_OPERATION={}\nBWFS_ITEM_ID={}\nBWFS_FIELD={}\nBWFS_UID={}\nBWFS_PID={}\nBWFS_EXE={}\n",
+                    entry.to_string().replace('\n', " "),
+                    entry.operation,
+                    entry.item_id,
+                    entry.field.replace('\n', " "),
+                    entry.uid,
+                    entry.pid,
+                    entry.exe.as_deref().unwrap_or_default().replace('\n', " "),
+                );
+                socket.send(message.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    sink: AuditSink,
+    recent: VecDeque<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new(sink: AuditSink) -> Self {
+        Self {
+            sink,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, entry: AuditEntry) {
+        if let Err(error) = self.sink.write(&entry) {
+            warn!(%error, "Failed to write audit entry");
+        }
+        if self.recent.len() == RECENT_ENTRIES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+    }
+
+    /// The most recent `count` entries, oldest first.
+    pub fn tail(&self, count: usize) -> Vec<AuditEntry> {
+        let skip = self.recent.len().saturating_sub(count);
+        self.recent.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditLogRef(pub Arc<Mutex<AuditLog>>);
+
+impl AuditLogRef {
+    pub fn record(&self, entry: AuditEntry) {
+        self.0.lock().unwrap().record(entry)
+    }
+
+    pub fn tail(&self, count: usize) -> Vec<AuditEntry> {
+        self.0.lock().unwrap().tail(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn entry(field: &str) -> AuditEntry {
+        AuditEntry::new(
+            AuditOperation::Read,
+            Uuid::from_u128(1),
+            field.to_owned(),
+            1000,
+            std::process::id(),
+        )
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bwfs-audit-{}-{name}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn file_sink_appends_json_lines() {
+        let path = temp_path("log");
+        let mut log = AuditLog::new(AuditSink::file(&path).unwrap());
+        log.record(entry("password"));
+        log.record(entry("totp"));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // reopening appends rather than truncating
+        let mut log = AuditLog::new(AuditSink::file(&path).unwrap());
+        log.record(entry("notes"));
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let fields: Vec<_> = content
+            .lines()
+            .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap().field)
+            .collect();
+        assert_eq!(fields, ["password", "totp", "notes"]);
+    }
+
+    #[test]
+    fn journal_sink_sends_fields() {
+        let path = temp_path("journal");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let mut log = AuditLog::new(AuditSink::journal_at(&path).unwrap());
+        log.record(entry("multi\nline"));
+        let mut buf = [0; 4096];
+        let n = journal.recv(&mut buf).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let message = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(message.contains("SYSLOG_IDENTIFIER=bwfs\n"));
+        assert!(message.contains("BWFS_OPERATION=read\n"));
+        assert!(message.contains("BWFS_ITEM_ID=00000000-0000-0000-0000-000000000001\n"));
+        assert!(message.contains("BWFS_FIELD=multi line\n"));
+        assert!(message.contains("BWFS_UID=1000\n"));
+        // one line per journal field
+        assert_eq!(message.lines().count(), 8);
+    }
+
+    #[test]
+    fn tail_keeps_recent_entries() {
+        let mut log = AuditLog::new(AuditSink::None);
+        for i in 0..RECENT_ENTRIES + 5 {
+            log.record(entry(&i.to_string()));
+        }
+        assert_eq!(log.recent.len(), RECENT_ENTRIES);
+        let fields: Vec<_> = log.tail(3).into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["1002", "1003", "1004"]);
+        let all = log.tail(usize::MAX);
+        assert_eq!(all.len(), RECENT_ENTRIES);
+        // the oldest entries were dropped
+        assert_eq!(all[0].field, "5");
+        assert!(log.tail(0).is_empty());
+    }
+}
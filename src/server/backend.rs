@@ -0,0 +1,59 @@
+use time::OffsetDateTime;
+
+use super::bwclient::{Folder, ItemError, Secret, SecretList, Status};
+use super::bwcrypto::Kdf;
+
+/// All folders and items in a vault.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Vault {
+    pub folders: Vec<Folder>,
+    pub secrets: Vec<Secret>,
+    /// Items that couldn't be parsed and are left out.
+    #[serde(default)]
+    pub errors: Vec<ItemError>,
+}
+
+/// A source of vault contents, such as the `bw` CLI.
+pub trait VaultBackend: Send {
+    /// Get the current lock status of the vault.
+    fn status(&self) -> anyhow::Result<Status>;
+
+    /// Unlock the vault with the master password.
+    fn unlock(&mut self, password: &str) -> anyhow::Result<()>;
+
+    /// Lock the vault, forgetting any session.
+    fn lock(&mut self);
+
+    /// List all items in the vault, along with any that couldn't be parsed.
+    fn list_secrets(&self) -> anyhow::Result<SecretList>;
+
+    /// List all folders in the vault.
+    fn list_folders(&self) -> anyhow::Result<Vec<Folder>>;
+
+    /// List all folders and items in the vault.
+    fn list_vault(&self) -> anyhow::Result<Vault> {
+        let folders = self.list_folders()?;
+        let SecretList { secrets, errors } = self.list_secrets()?;
+        Ok(Vault {
+            folders,
+            secrets,
+            errors,
+        })
+    }
+
+    /// Pull the latest vault contents from the server.
+    fn sync(&mut self) -> anyhow::Result<()>;
+
+    /// Replace an existing item with the given contents, returning the stored item.
+    fn edit(&self, secret: &Secret) -> anyhow::Result<Secret>;
+
+    /// Key derivation settings of the account, if known.
+    fn kdf(&self) -> Option<Kdf> {
+        None
+    }
+
+    /// When the data being served was last fetched, if it is a stale offline copy.
+    fn offline_since(&self) -> Option<OffsetDateTime> {
+        None
+    }
+}
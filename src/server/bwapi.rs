@@ -0,0 +1,862 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use time::OffsetDateTime;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::backend::VaultBackend;
+use super::bwclient::{
+    Folder, ItemError, LinkedId, Secret, SecretCard, SecretField, SecretFieldType, SecretList,
+    SecretLogin, SecretLoginUri, SecretPasswordHistory, SecretSshKey, SecretType, Status,
+    StatusKind, UriMatch,
+};
+use super::bwcrypto::{self, Kdf, SymmetricKey};
+
+/// How long before the access token expires to refresh it.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// Server `bw` uses when it isn't configured with one.
+pub const DEFAULT_SERVER_URL: &str = "https://vault.bitwarden.com";
+
+/// Backend talking directly to the Bitwarden (or Vaultwarden) API, decrypting the vault itself.
+pub struct BWApi {
+    identity_url: String,
+    api_url: String,
+    email: String,
+    device_id: Uuid,
+    kdf: Option<Kdf>,
+    session: Option<Session>,
+}
+
+/// Access to the API, renewed with the refresh token as the access token expires.
+#[derive(Debug, Clone)]
+struct Tokens {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires: Option<Instant>,
+}
+
+/// State held while the vault is unlocked.
+struct Session {
+    tokens: Tokens,
+    user_id: Uuid,
+    user_key: SymmetricKey,
+    org_keys: BTreeMap<Uuid, SymmetricKey>,
+    last_sync: OffsetDateTime,
+    sync: SyncResponse,
+}
+
+impl BWApi {
+    /// Create a backend for the account `email` on the server at `server_url`.
+    ///
+    /// The identity and API services are expected at `/identity` and `/api` under the server url,
+    /// as they are for self-hosted servers and the official cloud vaults.
+    pub fn new(server_url: &str, email: String) -> Self {
+        let server_url = server_url.trim_end_matches('/');
+        Self {
+            identity_url: format!("{server_url}/identity"),
+            api_url: format!("{server_url}/api"),
+            email,
+            device_id: Uuid::new_v4(),
+            kdf: None,
+            session: None,
+        }
+    }
+
+    fn token(&self, password_hash: &str) -> anyhow::Result<TokenResponse> {
+        let device_id = self.device_id.to_string();
+        self.request_token(&[
+            ("grant_type", "password"),
+            ("username", &self.email),
+            ("password", password_hash),
+            ("scope", "api offline_access"),
+            ("client_id", "cli"),
+            ("deviceType", "8"),
+            ("deviceIdentifier", &device_id),
+            ("deviceName", "bwfs"),
+        ])
+    }
+
+    /// Swap the refresh token for a new access token.
+    fn refresh(&self, tokens: &mut Tokens) -> anyhow::Result<()> {
+        let refresh_token = tokens
+            .refresh_token
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Access token expired and there is no refresh token"))?;
+        debug!("Refreshing access token");
+        let response = self.request_token(&[
+            ("grant_type", "refresh_token"),
+            ("client_id", "cli"),
+            ("refresh_token", refresh_token),
+        ])?;
+        *tokens = Tokens::new(response, tokens.refresh_token.take());
+        Ok(())
+    }
+
+    fn request_token(&self, form: &[(&str, &str)]) -> anyhow::Result<TokenResponse> {
+        let response = ureq::post(&format!("{}/connect/token", self.identity_url))
+            .set("Auth-Email", &URL_SAFE_NO_PAD.encode(&self.email))
+            .send_form(form);
+        match response {
+            Ok(response) => Ok(response.into_json()?),
+            Err(ureq::Error::Status(_, response)) => {
+                let error: TokenError = response.into_json()?;
+                Err(anyhow::anyhow!(error
+                    .error_description
+                    .or(error.error)
+                    .unwrap_or_else(|| "Login failed".to_owned())))
+            }
+            Err(e) => Err(anyhow::Error::from(e)),
+        }
+    }
+
+    /// Fetch the vault, refreshing the access token when it is about to expire or is rejected.
+    fn fetch_sync(&self, tokens: &mut Tokens) -> anyhow::Result<SyncResponse> {
+        if tokens
+            .expires
+            .is_some_and(|expires| Instant::now() + TOKEN_MARGIN >= expires)
+        {
+            self.refresh(tokens)?;
+        }
+        let url = format!("{}/sync?excludeDomains=true", self.api_url);
+        let mut refreshed = false;
+        loop {
+            let response = ureq::get(&url)
+                .set("Authorization", &format!("Bearer {}", tokens.access_token))
+                .call();
+            match response {
+                Err(ureq::Error::Status(401, _))
+                    if !refreshed && tokens.refresh_token.is_some() =>
+                {
+                    debug!("Access token rejected");
+                    self.refresh(tokens)?;
+                    refreshed = true;
+                }
+                response => return Ok(response?.into_json()?),
+            }
+        }
+    }
+
+    fn session(&self) -> anyhow::Result<&Session> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Vault is locked"))
+    }
+}
+
+impl Tokens {
+    /// Tokens from a token response, keeping `refresh_token` if it didn't include a new one.
+    fn new(response: TokenResponse, refresh_token: Option<String>) -> Self {
+        Self {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.or(refresh_token),
+            expires: response
+                .expires_in
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+        }
+    }
+}
+
+impl Session {
+    fn new(tokens: Tokens, user_key: SymmetricKey, sync: SyncResponse) -> anyhow::Result<Self> {
+        let mut org_keys = BTreeMap::new();
+        if !sync.profile.organizations.is_empty() {
+            let private_key =
+                sync.profile.private_key.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("Account has organizations but no private key")
+                })?;
+            let private_key = user_key.decrypt(private_key)?;
+            for org in &sync.profile.organizations {
+                let key = bwcrypto::decrypt_rsa(&org.key, &private_key)
+                    .and_then(|k| SymmetricKey::from_bytes(&k));
+                match key {
+                    Ok(key) => {
+                        org_keys.insert(org.id, key);
+                    }
+                    Err(error) => {
+                        warn!(%error, org = %org.id, "Failed to decrypt organization key")
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            tokens,
+            user_id: sync.profile.id,
+            user_key,
+            org_keys,
+            last_sync: OffsetDateTime::now_utc(),
+            sync,
+        })
+    }
+
+    /// The key for a cipher, which may have its own key encrypted with the user or org key.
+    fn cipher_key(&self, cipher: &CipherResponse) -> anyhow::Result<SymmetricKey> {
+        let key = match cipher.organization_id {
+            Some(org) => self
+                .org_keys
+                .get(&org)
+                .ok_or_else(|| anyhow::anyhow!("Missing key for organization {org}"))?,
+            None => &self.user_key,
+        };
+        match &cipher.key {
+            Some(cipher_key) => SymmetricKey::from_bytes(&key.decrypt(cipher_key)?),
+            None => Ok(key.clone()),
+        }
+    }
+
+    fn decrypt_cipher(&self, cipher: &CipherResponse) -> anyhow::Result<Secret> {
+        let key = self.cipher_key(cipher)?;
+        let decrypt = |s: &str| key.decrypt_str(s);
+        let decrypt_opt = |s: &Option<String>| s.as_deref().map(decrypt).transpose();
+        let login = cipher
+            .login
+            .as_ref()
+            .map(|login| -> anyhow::Result<_> {
+                Ok(SecretLogin {
+                    fido_2_credentials: Vec::new(),
+                    uris: login
+                        .uris
+                        .as_ref()
+                        .map(|uris| {
+                            uris.iter()
+                                .map(|u| {
+                                    Ok(SecretLoginUri {
+                                        r#match: u.r#match.map(UriMatch::from),
+                                        uri: decrypt_opt(&u.uri)?.unwrap_or_default(),
+                                    })
+                                })
+                                .collect::<anyhow::Result<_>>()
+                        })
+                        .transpose()?,
+                    username: decrypt_opt(&login.username)?,
+                    password: decrypt_opt(&login.password)?,
+                    totp: decrypt_opt(&login.totp)?,
+                    password_revision_date: login.password_revision_date,
+                })
+            })
+            .transpose()?;
+        let card = cipher
+            .card
+            .as_ref()
+            .map(|card| -> anyhow::Result<_> {
+                Ok(SecretCard {
+                    cardholder_name: decrypt_opt(&card.cardholder_name)?,
+                    brand: decrypt_opt(&card.brand)?,
+                    number: decrypt_opt(&card.number)?,
+                    exp_month: decrypt_opt(&card.exp_month)?,
+                    exp_year: decrypt_opt(&card.exp_year)?,
+                    code: decrypt_opt(&card.code)?,
+                })
+            })
+            .transpose()?;
+        let ssh_key = cipher
+            .ssh_key
+            .as_ref()
+            .map(|key| -> anyhow::Result<_> {
+                Ok(SecretSshKey {
+                    private_key: decrypt(&key.private_key)?,
+                    public_key: decrypt(&key.public_key)?,
+                    key_fingerprint: decrypt(&key.key_fingerprint)?,
+                })
+            })
+            .transpose()?;
+        let fields = cipher
+            .fields
+            .as_ref()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|f| {
+                        Ok(SecretField {
+                            name: decrypt_opt(&f.name)?.unwrap_or_default(),
+                            value: decrypt_opt(&f.value)?,
+                            r#type: f.r#type,
+                            linked_id: f.linked_id,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()
+            })
+            .transpose()?;
+        let password_history = cipher
+            .password_history
+            .as_ref()
+            .map(|history| {
+                history
+                    .iter()
+                    .map(|h| {
+                        Ok(SecretPasswordHistory {
+                            last_used_date: h.last_used_date,
+                            password: decrypt(&h.password)?,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()
+            })
+            .transpose()?;
+        Ok(Secret {
+            password_history,
+            revision_date: cipher.revision_date,
+            creation_date: cipher.creation_date,
+            deleted_date: cipher.deleted_date,
+            object: "item".to_owned(),
+            id: cipher.id,
+            organization_id: cipher.organization_id,
+            folder_id: cipher.folder_id,
+            r#type: cipher.r#type,
+            reprompt: cipher.reprompt,
+            name: decrypt(&cipher.name)?,
+            notes: decrypt_opt(&cipher.notes)?,
+            favorite: cipher.favorite,
+            fields,
+            login,
+            card,
+            ssh_key,
+            collection_ids: cipher.collection_ids.clone(),
+        })
+    }
+}
+
+impl VaultBackend for BWApi {
+    fn status(&self) -> anyhow::Result<Status> {
+        let status = match &self.session {
+            Some(session) => Status {
+                last_sync: session.last_sync,
+                user_email: self.email.clone(),
+                user_id: session.user_id,
+                status: StatusKind::Unlocked,
+                server_url: None,
+            },
+            None => Status {
+                last_sync: OffsetDateTime::UNIX_EPOCH,
+                user_email: self.email.clone(),
+                user_id: Uuid::nil(),
+                status: StatusKind::Locked,
+                server_url: None,
+            },
+        };
+        Ok(status)
+    }
+
+    fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
+        debug!("Unlocking vault");
+        let kdf = prelogin(&self.identity_url, &self.email)?;
+        self.kdf = Some(kdf);
+        let master_key = bwcrypto::master_key(password, &self.email, kdf)?;
+        let password_hash = bwcrypto::master_password_hash(&master_key, password);
+        let mut token = self.token(&password_hash)?;
+        debug!("Got access token");
+        let stretched = SymmetricKey::stretch(&master_key);
+        let key = token.key.take();
+        let mut tokens = Tokens::new(token, None);
+        let sync = self.fetch_sync(&mut tokens)?;
+        let encrypted_user_key = key
+            .as_deref()
+            .or(sync.profile.key.as_deref())
+            .ok_or_else(|| anyhow::anyhow!("Server did not return a user key"))?;
+        let user_key = SymmetricKey::from_bytes(&stretched.decrypt(encrypted_user_key)?)?;
+        self.session = Some(Session::new(tokens, user_key, sync)?);
+        info!("Vault unlocked");
+        Ok(())
+    }
+
+    fn lock(&mut self) {
+        self.session = None;
+    }
+
+    fn list_secrets(&self) -> anyhow::Result<SecretList> {
+        let session = self.session()?;
+        let mut list = SecretList::default();
+        for cipher in &session.sync.ciphers {
+            if cipher.deleted_date.is_some() {
+                continue;
+            }
+            match session.decrypt_cipher(cipher) {
+                Ok(secret) => list.secrets.push(secret),
+                Err(error) => {
+                    warn!(%error, id = %cipher.id, "Failed to decrypt item");
+                    list.errors.push(ItemError {
+                        id: Some(cipher.id),
+                        name: None,
+                        error: format!("Failed to decrypt: {error}"),
+                    });
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
+        let session = self.session()?;
+        let mut folders = session
+            .sync
+            .folders
+            .iter()
+            .map(|f| {
+                Ok(Folder {
+                    object: "folder".to_owned(),
+                    id: Some(f.id),
+                    name: session.user_key.decrypt_str(&f.name)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // match the bw cli, which lists a folder for items without one
+        folders.push(Folder {
+            object: "folder".to_owned(),
+            id: None,
+            name: "No Folder".to_owned(),
+        });
+        Ok(folders)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        let mut tokens = self.session()?.tokens.clone();
+        let sync = self.fetch_sync(&mut tokens);
+        let session = self.session.as_mut().expect("session checked above");
+        // keep any refreshed tokens, even if the sync itself failed
+        session.tokens = tokens;
+        let sync = sync?;
+        session.sync = sync;
+        session.last_sync = OffsetDateTime::now_utc();
+        Ok(())
+    }
+
+    fn edit(&self, _secret: &Secret) -> anyhow::Result<Secret> {
+        anyhow::bail!("Editing items is not supported by the api backend")
+    }
+
+    fn kdf(&self) -> Option<Kdf> {
+        self.kdf
+    }
+}
+
+/// Look up the key derivation settings of the account `email` from the identity service.
+fn prelogin(identity_url: &str, email: &str) -> anyhow::Result<Kdf> {
+    let response: PreloginResponse = ureq::post(&format!("{identity_url}/accounts/prelogin"))
+        .send_json(serde_json::json!({ "email": email }))?
+        .into_json()?;
+    debug!(?response, "Got prelogin");
+    Ok(match response.kdf {
+        0 => Kdf::Pbkdf2 {
+            iterations: response.kdf_iterations,
+        },
+        1 => Kdf::Argon2id {
+            iterations: response.kdf_iterations,
+            memory: response.kdf_memory.unwrap_or(64),
+            parallelism: response.kdf_parallelism.unwrap_or(4),
+        },
+        kdf => anyhow::bail!("Unsupported kdf type {kdf}"),
+    })
+}
+
+/// Look up the key derivation settings of the account `bw` is logged in to, as reported by its
+/// status.
+pub fn account_kdf(status: &Status) -> Option<Kdf> {
+    let server_url = status
+        .server_url
+        .as_deref()
+        .unwrap_or(DEFAULT_SERVER_URL)
+        .trim_end_matches('/');
+    match prelogin(&format!("{server_url}/identity"), &status.user_email) {
+        Ok(kdf) => Some(kdf),
+        Err(error) => {
+            warn!(%error, "Failed to look up the account's kdf");
+            None
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PreloginResponse {
+    kdf: u8,
+    kdf_iterations: u32,
+    kdf_memory: Option<u32>,
+    kdf_parallelism: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    /// Seconds until the access token expires.
+    expires_in: Option<u64>,
+    #[serde(rename = "Key", alias = "key")]
+    key: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenError {
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncResponse {
+    profile: ProfileResponse,
+    #[serde(default)]
+    folders: Vec<FolderResponse>,
+    #[serde(default)]
+    ciphers: Vec<CipherResponse>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileResponse {
+    id: Uuid,
+    key: Option<String>,
+    private_key: Option<String>,
+    #[serde(default)]
+    organizations: Vec<OrganizationResponse>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrganizationResponse {
+    id: Uuid,
+    key: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderResponse {
+    id: Uuid,
+    name: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CipherResponse {
+    id: Uuid,
+    organization_id: Option<Uuid>,
+    folder_id: Option<Uuid>,
+    r#type: SecretType,
+    #[serde(default)]
+    reprompt: u32,
+    name: String,
+    notes: Option<String>,
+    #[serde(default)]
+    favorite: bool,
+    key: Option<String>,
+    login: Option<LoginResponse>,
+    card: Option<CardResponse>,
+    ssh_key: Option<SshKeyResponse>,
+    fields: Option<Vec<FieldResponse>>,
+    password_history: Option<Vec<PasswordHistoryResponse>>,
+    #[serde(with = "time::serde::rfc3339")]
+    revision_date: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    creation_date: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    deleted_date: Option<OffsetDateTime>,
+    #[serde(default)]
+    collection_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginResponse {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Option<Vec<LoginUriResponse>>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    password_revision_date: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginUriResponse {
+    uri: Option<String>,
+    r#match: Option<u8>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardResponse {
+    cardholder_name: Option<String>,
+    brand: Option<String>,
+    number: Option<String>,
+    exp_month: Option<String>,
+    exp_year: Option<String>,
+    code: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SshKeyResponse {
+    private_key: String,
+    public_key: String,
+    key_fingerprint: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FieldResponse {
+    name: Option<String>,
+    value: Option<String>,
+    r#type: SecretFieldType,
+    #[serde(default)]
+    linked_id: Option<LinkedId>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PasswordHistoryResponse {
+    password: String,
+    #[serde(with = "time::serde::rfc3339")]
+    last_used_date: OffsetDateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "user@example.com";
+    const PASSWORD: &str = "hunter2";
+    const ITERATIONS: u32 = 1000;
+    const FOLDER_ID: &str = "1f0bc8fe-4d0e-4b0c-9a3e-6c1e1f0bc8fe";
+    const ITEM_ID: &str = "2b5c9a5e-8a0b-4d7e-8f1d-2b5c9a5e8a0b";
+
+    const PBKDF2: Kdf = Kdf::Pbkdf2 {
+        iterations: ITERATIONS,
+    };
+
+    fn mock_server() -> String {
+        mock_server_with(PBKDF2, false)
+    }
+
+    /// Serve the prelogin, token and sync endpoints for a single user vault, with the account
+    /// using `kdf`.
+    ///
+    /// Only the latest access token is accepted, and never the first one with
+    /// `reject_first_token`, as if it had expired.
+    fn mock_server_with(kdf: Kdf, reject_first_token: bool) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+
+        let master_key = bwcrypto::master_key(PASSWORD, EMAIL, kdf).unwrap();
+        let password_hash = bwcrypto::master_password_hash(&master_key, PASSWORD);
+        let user_key_bytes = [7; 64];
+        let encrypted_user_key =
+            SymmetricKey::stretch(&master_key).encrypt(&user_key_bytes, [1; 16]);
+        let user_key = SymmetricKey::from_bytes(&user_key_bytes).unwrap();
+        let enc = |s: &str| user_key.encrypt(s.as_bytes(), [2; 16]);
+        let sync = serde_json::json!({
+            "object": "sync",
+            "profile": {
+                "id": "5d3a8c1e-1b2c-4d5e-8f90-5d3a8c1e1b2c",
+                "email": EMAIL,
+                "key": encrypted_user_key,
+                "privateKey": null,
+                "organizations": [],
+            },
+            "folders": [{ "id": FOLDER_ID, "name": enc("web/work"), "revisionDate": "2024-01-01T00:00:00Z" }],
+            "ciphers": [
+                {
+                    "id": ITEM_ID,
+                    "organizationId": null,
+                    "folderId": FOLDER_ID,
+                    "type": 1,
+                    "reprompt": 0,
+                    "name": enc("github"),
+                    "notes": enc("some notes"),
+                    "favorite": true,
+                    "key": null,
+                    "login": {
+                        "username": enc("octocat"),
+                        "password": enc("correct horse"),
+                        "totp": null,
+                        "uris": [{ "uri": enc("https://github.com"), "match": null }],
+                        "passwordRevisionDate": null,
+                    },
+                    "fields": [{ "name": enc("pin"), "value": enc("1234"), "type": 1, "linkedId": null }],
+                    "passwordHistory": null,
+                    "revisionDate": "2024-01-02T00:00:00Z",
+                    "creationDate": "2024-01-01T00:00:00Z",
+                    "deletedDate": null,
+                    "collectionIds": [],
+                },
+                {
+                    "id": "3c6d0b6f-9b1c-4e8f-901e-3c6d0b6f9b1c",
+                    "organizationId": null,
+                    "folderId": null,
+                    "type": 2,
+                    "name": enc("deleted card"),
+                    "revisionDate": "2024-01-02T00:00:00Z",
+                    "creationDate": "2024-01-01T00:00:00Z",
+                    "deletedDate": "2024-01-03T00:00:00Z",
+                }
+            ],
+        })
+        .to_string();
+
+        let prelogin = match kdf {
+            Kdf::Pbkdf2 { iterations } => {
+                serde_json::json!({ "kdf": 0, "kdfIterations": iterations })
+            }
+            Kdf::Argon2id {
+                iterations,
+                memory,
+                parallelism,
+            } => serde_json::json!({
+                "kdf": 1,
+                "kdfIterations": iterations,
+                "kdfMemory": memory,
+                "kdfParallelism": parallelism,
+            }),
+        }
+        .to_string();
+
+        std::thread::spawn(move || {
+            let mut issued = 0;
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let (status, response) = match request.url() {
+                    "/identity/accounts/prelogin" => (200, prelogin.clone()),
+                    "/identity/connect/token" => {
+                        let encoded = password_hash
+                            .replace('+', "%2B")
+                            .replace('/', "%2F")
+                            .replace('=', "%3D");
+                        let refresh = body.contains("grant_type=refresh_token")
+                            && body.contains("refresh_token=refresh");
+                        if refresh || body.contains(&format!("password={encoded}")) {
+                            issued += 1;
+                            let mut token = serde_json::json!({
+                                "access_token": format!("token-{issued}"),
+                                "expires_in": 3600,
+                                "refresh_token": "refresh",
+                            });
+                            if !refresh {
+                                token["Key"] = encrypted_user_key.clone().into();
+                            }
+                            (200, token.to_string())
+                        } else {
+                            (
+                                400,
+                                serde_json::json!({ "error": "invalid_grant", "error_description": "Username or password is incorrect" })
+                                    .to_string(),
+                            )
+                        }
+                    }
+                    "/api/sync?excludeDomains=true" => {
+                        let bearer = format!("Bearer token-{issued}");
+                        let authorized = !(reject_first_token && issued == 1)
+                            && request
+                                .headers()
+                                .iter()
+                                .any(|h| h.field.equiv("Authorization") && h.value == bearer);
+                        if authorized {
+                            (200, sync.clone())
+                        } else {
+                            (401, String::new())
+                        }
+                    }
+                    _ => (404, String::new()),
+                };
+                let response = tiny_http::Response::from_string(response)
+                    .with_status_code(status)
+                    .with_header(
+                        "Content-Type: application/json"
+                            .parse::<tiny_http::Header>()
+                            .unwrap(),
+                    );
+                request.respond(response).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn unlock_and_decrypt() {
+        let url = mock_server();
+        let mut api = BWApi::new(&url, EMAIL.to_owned());
+        assert_eq!(api.status().unwrap().status, StatusKind::Locked);
+        assert!(api.list_secrets().is_err());
+
+        api.unlock(PASSWORD).unwrap();
+        assert_eq!(api.status().unwrap().status, StatusKind::Unlocked);
+
+        let folders = api.list_folders().unwrap();
+        let names: Vec<_> = folders.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["web/work", "No Folder"]);
+
+        let secrets = api.list_secrets().unwrap().secrets;
+        assert_eq!(secrets.len(), 1, "deleted items are skipped");
+        let secret = &secrets[0];
+        assert_eq!(secret.id.to_string(), ITEM_ID);
+        assert_eq!(secret.name, "github");
+        assert_eq!(secret.notes.as_deref(), Some("some notes"));
+        let login = secret.login.as_ref().unwrap();
+        assert_eq!(login.username.as_deref(), Some("octocat"));
+        assert_eq!(login.password.as_deref(), Some("correct horse"));
+        assert_eq!(login.uris.as_ref().unwrap()[0].uri, "https://github.com");
+        let fields = secret.fields.as_ref().unwrap();
+        assert_eq!(fields[0].name, "pin");
+        assert_eq!(fields[0].value.as_deref(), Some("1234"));
+
+        api.sync().unwrap();
+        assert_eq!(api.list_secrets().unwrap().secrets.len(), 1);
+
+        api.lock();
+        assert_eq!(api.status().unwrap().status, StatusKind::Locked);
+        assert!(api.list_secrets().is_err());
+    }
+
+    #[test]
+    fn refreshes_rejected_token() {
+        let url = mock_server_with(PBKDF2, true);
+        let mut api = BWApi::new(&url, EMAIL.to_owned());
+        api.unlock(PASSWORD).unwrap();
+        assert_eq!(api.session().unwrap().tokens.access_token, "token-2");
+        assert_eq!(api.list_secrets().unwrap().secrets.len(), 1);
+
+        // an access token about to expire is refreshed before syncing
+        api.session.as_mut().unwrap().tokens.expires = Some(Instant::now());
+        api.sync().unwrap();
+        assert_eq!(api.session().unwrap().tokens.access_token, "token-3");
+    }
+
+    #[test]
+    fn unlock_with_argon2() {
+        let kdf = Kdf::Argon2id {
+            iterations: 2,
+            memory: 1,
+            parallelism: 1,
+        };
+        let url = mock_server_with(kdf, false);
+        let mut api = BWApi::new(&url, EMAIL.to_owned());
+        api.unlock(PASSWORD).unwrap();
+        assert_eq!(api.kdf(), Some(kdf));
+        assert_eq!(api.list_secrets().unwrap().secrets[0].name, "github");
+    }
+
+    #[test]
+    fn wrong_password() {
+        let url = mock_server();
+        let mut api = BWApi::new(&url, EMAIL.to_owned());
+        let error = api.unlock("wrong").unwrap_err();
+        assert_eq!(error.to_string(), "Username or password is incorrect");
+        assert_eq!(api.status().unwrap().status, StatusKind::Locked);
+    }
+
+    #[test]
+    fn kdf_for_bw_account() {
+        let url = mock_server();
+        let mut status = BWApi::new(&url, EMAIL.to_owned()).status().unwrap();
+        status.server_url = Some(format!("{url}/"));
+        assert_eq!(
+            account_kdf(&status),
+            Some(Kdf::Pbkdf2 {
+                iterations: ITERATIONS
+            })
+        );
+        status.server_url = Some("http://127.0.0.1:1".to_owned());
+        assert_eq!(account_kdf(&status), None);
+    }
+}
@@ -1,8 +1,18 @@
-use std::{fmt::Display, process::Command};
+use base64::Engine;
+use serde::Deserialize;
+use std::{
+    fmt::Display,
+    io::Write,
+    process::{Command, Stdio},
+};
 use time::OffsetDateTime;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use super::backend::VaultBackend;
+use super::bwapi::account_kdf;
+use super::bwcrypto::Kdf;
+
 pub struct BWCLI {
     path: String,
     session_token: Option<String>,
@@ -26,8 +36,10 @@ impl BWCLI {
         }
         cmd
     }
+}
 
-    pub fn status(&self) -> anyhow::Result<Status> {
+impl VaultBackend for BWCLI {
+    fn status(&self) -> anyhow::Result<Status> {
         let output = self.command(&["status"]).output()?;
         let stdout = String::from_utf8(output.stdout)?;
         let status: Status = serde_json::from_str(&stdout)?;
@@ -35,7 +47,7 @@ impl BWCLI {
         Ok(status)
     }
 
-    pub fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
+    fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
         const BWFS_PASSWORD: &str = "BWFS_PASSWORD";
         debug!("Unlocking vault");
         let output = self
@@ -54,23 +66,62 @@ impl BWCLI {
         }
     }
 
-    pub fn lock(&mut self) {
+    fn lock(&mut self) {
         self.session_token = None;
     }
 
-    pub fn list_secrets(&self) -> anyhow::Result<Vec<Secret>> {
+    fn list_secrets(&self) -> anyhow::Result<SecretList> {
         let output = self.command(&["list", "items"]).output()?;
         let stdout = String::from_utf8(output.stdout)?;
-        let secrets_list: Vec<Secret> = serde_json::from_str(&stdout)?;
-        Ok(secrets_list)
+        let items: Vec<serde_json::Value> = serde_json::from_str(&stdout)?;
+        Ok(SecretList::parse(items))
     }
 
-    pub fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
+    fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
         let output = self.command(&["list", "folders"]).output()?;
         let stdout = String::from_utf8(output.stdout)?;
         let folders_list: Vec<Folder> = serde_json::from_str(&stdout)?;
         Ok(folders_list)
     }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        let output = self.command(&["sync"]).output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                String::from_utf8(output.stderr).unwrap_or_default()
+            ))
+        }
+    }
+
+    fn edit(&self, secret: &Secret) -> anyhow::Result<Secret> {
+        let json = serde_json::to_vec(secret)?;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(json);
+        let id = secret.id.to_string();
+        // passed on stdin, as arguments can be read by any user through /proc
+        let mut child = self
+            .command(&["edit", "item", &id])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(encoded.as_bytes())?;
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            let stdout = String::from_utf8(output.stdout)?;
+            let secret: Secret = serde_json::from_str(&stdout)?;
+            Ok(secret)
+        } else {
+            Err(anyhow::anyhow!(
+                String::from_utf8(output.stderr).unwrap_or_default()
+            ))
+        }
+    }
+
+    fn kdf(&self) -> Option<Kdf> {
+        account_kdf(&self.status().ok()?)
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -81,6 +132,9 @@ pub struct Status {
     pub user_email: String,
     pub user_id: Uuid,
     pub status: StatusKind,
+    /// Server `bw` is logged in to, `None` for the default cloud vault or other backends.
+    #[serde(default)]
+    pub server_url: Option<String>,
 }
 
 #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -91,7 +145,7 @@ pub enum StatusKind {
     Unauthenticated,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Secret {
     pub password_history: Option<Vec<SecretPasswordHistory>>,
@@ -112,16 +166,84 @@ pub struct Secret {
     pub favorite: bool,
     pub fields: Option<Vec<SecretField>>,
     pub login: Option<SecretLogin>,
+    pub card: Option<SecretCard>,
+    pub ssh_key: Option<SecretSshKey>,
     pub collection_ids: Vec<Uuid>,
 }
 
-#[derive(Debug, serde_repr::Serialize_repr, serde_repr::Deserialize_repr)]
-#[repr(u8)]
+/// An item left out of the vault because it couldn't be parsed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ItemError {
+    pub id: Option<Uuid>,
+    pub name: Option<String>,
+    pub error: String,
+}
+
+/// Items listed from a vault, along with those that couldn't be parsed.
+#[derive(Debug, Clone, Default)]
+pub struct SecretList {
+    pub secrets: Vec<Secret>,
+    pub errors: Vec<ItemError>,
+}
+
+impl SecretList {
+    /// Parse each item on its own, so one unexpected item doesn't fail the whole list.
+    pub fn parse(items: Vec<serde_json::Value>) -> Self {
+        let mut list = Self::default();
+        for item in items {
+            match Secret::deserialize(&item) {
+                Ok(secret) => list.secrets.push(secret),
+                Err(error) => {
+                    let error = ItemError {
+                        id: item["id"].as_str().and_then(|id| id.parse().ok()),
+                        name: item["name"].as_str().map(str::to_owned),
+                        error: error.to_string(),
+                    };
+                    warn!(id = ?error.id, error.error, "Skipping item that failed to parse");
+                    list.errors.push(error);
+                }
+            }
+        }
+        list
+    }
+}
+
+/// Kind of item, with types added after this was written kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "u8", into = "u8")]
 pub enum SecretType {
-    Login = 1,
-    Card = 2,
-    Identity = 3,
-    SecureNote = 4,
+    Login,
+    Card,
+    Identity,
+    SecureNote,
+    SshKey,
+    Other(u8),
+}
+
+impl From<u8> for SecretType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => SecretType::Login,
+            2 => SecretType::Card,
+            3 => SecretType::Identity,
+            4 => SecretType::SecureNote,
+            5 => SecretType::SshKey,
+            other => SecretType::Other(other),
+        }
+    }
+}
+
+impl From<SecretType> for u8 {
+    fn from(value: SecretType) -> Self {
+        match value {
+            SecretType::Login => 1,
+            SecretType::Card => 2,
+            SecretType::Identity => 3,
+            SecretType::SecureNote => 4,
+            SecretType::SshKey => 5,
+            SecretType::Other(other) => other,
+        }
+    }
 }
 
 impl Display for SecretType {
@@ -131,15 +253,18 @@ impl Display for SecretType {
             SecretType::Card => "Card",
             SecretType::Identity => "Identity",
             SecretType::SecureNote => "Secure note",
+            SecretType::SshKey => "SSH key",
+            SecretType::Other(other) => return write!(f, "Unknown ({other})"),
         };
         write!(f, "{}", s)
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SecretLogin {
-    pub fido_2_credentials: Vec<String>,
+    #[serde(default)]
+    pub fido_2_credentials: Vec<SecretFido2Credential>,
     pub uris: Option<Vec<SecretLoginUri>>,
     pub username: Option<String>,
     pub password: Option<String>,
@@ -148,14 +273,102 @@ pub struct SecretLogin {
     pub password_revision_date: Option<OffsetDateTime>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// A passkey stored with a login.
+///
+/// The CLI exports most values as strings, so they are kept as such.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretFido2Credential {
+    pub credential_id: Option<String>,
+    pub key_type: Option<String>,
+    pub key_algorithm: Option<String>,
+    pub key_curve: Option<String>,
+    pub key_value: Option<String>,
+    pub rp_id: Option<String>,
+    pub rp_name: Option<String>,
+    pub user_handle: Option<String>,
+    pub user_name: Option<String>,
+    pub user_display_name: Option<String>,
+    pub counter: Option<String>,
+    pub discoverable: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub creation_date: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SecretLoginUri {
-    pub r#match: Option<String>,
+    pub r#match: Option<UriMatch>,
     pub uri: String,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// How a login URI is compared to the URL being visited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "u8", into = "u8")]
+pub enum UriMatch {
+    /// Same base domain, e.g. `example.com` for `git.example.com`.
+    Domain,
+    /// Same host and port.
+    Host,
+    StartsWith,
+    Exact,
+    RegularExpression,
+    Never,
+    Other(u8),
+}
+
+impl From<u8> for UriMatch {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => UriMatch::Domain,
+            1 => UriMatch::Host,
+            2 => UriMatch::StartsWith,
+            3 => UriMatch::Exact,
+            4 => UriMatch::RegularExpression,
+            5 => UriMatch::Never,
+            other => UriMatch::Other(other),
+        }
+    }
+}
+
+impl From<UriMatch> for u8 {
+    fn from(value: UriMatch) -> Self {
+        match value {
+            UriMatch::Domain => 0,
+            UriMatch::Host => 1,
+            UriMatch::StartsWith => 2,
+            UriMatch::Exact => 3,
+            UriMatch::RegularExpression => 4,
+            UriMatch::Never => 5,
+            UriMatch::Other(other) => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretCard {
+    pub cardholder_name: Option<String>,
+    pub brand: Option<String>,
+    pub number: Option<String>,
+    pub exp_month: Option<String>,
+    pub exp_year: Option<String>,
+    /// Security code.
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretSshKey {
+    /// OpenSSH private key.
+    pub private_key: String,
+    /// OpenSSH public key, as found in `authorized_keys`.
+    pub public_key: String,
+    /// SHA256 fingerprint of the public key.
+    pub key_fingerprint: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SecretPasswordHistory {
     #[serde(with = "time::serde::rfc3339")]
@@ -163,27 +376,161 @@ pub struct SecretPasswordHistory {
     pub password: String,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SecretField {
     pub name: String,
-    pub value: String,
+    /// Null for linked fields, which take their value from the property they point at.
+    pub value: Option<String>,
     pub r#type: SecretFieldType,
+    /// Property of the item a linked field takes its value from.
+    #[serde(default)]
+    pub linked_id: Option<LinkedId>,
 }
 
-#[derive(Debug, serde_repr::Serialize_repr, serde_repr::Deserialize_repr)]
-#[repr(u8)]
+/// Kind of custom field, with types added after this was written kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "u8", into = "u8")]
 pub enum SecretFieldType {
-    Text = 0,
-    Hidden = 1,
-    Boolean = 2,
-    Linked = 3,
+    Text,
+    Hidden,
+    Boolean,
+    Linked,
+    Other(u8),
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+impl From<u8> for SecretFieldType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => SecretFieldType::Text,
+            1 => SecretFieldType::Hidden,
+            2 => SecretFieldType::Boolean,
+            3 => SecretFieldType::Linked,
+            other => SecretFieldType::Other(other),
+        }
+    }
+}
+
+impl From<SecretFieldType> for u8 {
+    fn from(value: SecretFieldType) -> Self {
+        match value {
+            SecretFieldType::Text => 0,
+            SecretFieldType::Hidden => 1,
+            SecretFieldType::Boolean => 2,
+            SecretFieldType::Linked => 3,
+            SecretFieldType::Other(other) => other,
+        }
+    }
+}
+
+/// Item property a linked custom field points at, with those not shown as files kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "u16", into = "u16")]
+pub enum LinkedId {
+    LoginUsername,
+    LoginPassword,
+    CardCardholderName,
+    CardExpMonth,
+    CardExpYear,
+    CardCode,
+    CardBrand,
+    CardNumber,
+    Other(u16),
+}
+
+impl From<u16> for LinkedId {
+    fn from(value: u16) -> Self {
+        match value {
+            100 => LinkedId::LoginUsername,
+            101 => LinkedId::LoginPassword,
+            300 => LinkedId::CardCardholderName,
+            301 => LinkedId::CardExpMonth,
+            302 => LinkedId::CardExpYear,
+            303 => LinkedId::CardCode,
+            304 => LinkedId::CardBrand,
+            305 => LinkedId::CardNumber,
+            other => LinkedId::Other(other),
+        }
+    }
+}
+
+impl From<LinkedId> for u16 {
+    fn from(value: LinkedId) -> Self {
+        match value {
+            LinkedId::LoginUsername => 100,
+            LinkedId::LoginPassword => 101,
+            LinkedId::CardCardholderName => 300,
+            LinkedId::CardExpMonth => 301,
+            LinkedId::CardExpYear => 302,
+            LinkedId::CardCode => 303,
+            LinkedId::CardBrand => 304,
+            LinkedId::CardNumber => 305,
+            LinkedId::Other(other) => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Folder {
     pub object: String,
     pub id: Option<Uuid>,
     pub name: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::PASSWORD;
+
+    #[test]
+    fn edit_sends_item_on_stdin() {
+        let bw = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/bw");
+        let mut cli = BWCLI::new(bw.to_owned());
+        cli.unlock(PASSWORD).unwrap();
+        let mut secret = cli.list_secrets().unwrap().secrets.remove(0);
+        secret.notes = Some("moved the recovery codes".to_owned());
+
+        // the fake bw refuses the item as an argument
+        let edited = cli.edit(&secret).unwrap();
+        assert_eq!(edited.id, secret.id);
+        assert_eq!(edited.notes, secret.notes);
+    }
+
+    #[test]
+    fn parse_skips_broken_items() {
+        let mut items: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../tests/fixtures/items.json")).unwrap();
+        let total = items.len();
+        items[0]["type"] = 42.into();
+        items[0]["login"]["fido2Credentials"] = serde_json::json!([{
+            "credentialId": "b0a8e3c1",
+            "keyType": "public-key",
+            "rpId": "github.com",
+            "counter": "0",
+            "discoverable": "true",
+            "creationDate": "2024-01-01T10:00:00.000Z",
+            "someNewField": 1
+        }]);
+        items[0]["fields"][0]["type"] = 9.into();
+        items[1]["revisionDate"] = "yesterday".into();
+
+        let list = SecretList::parse(items);
+        assert_eq!(list.secrets.len(), total - 1);
+        let github = &list.secrets[0];
+        assert_eq!(github.r#type, SecretType::Other(42));
+        assert_eq!(github.r#type.to_string(), "Unknown (42)");
+        assert_eq!(
+            github.fields.as_ref().unwrap()[0].r#type,
+            SecretFieldType::Other(9)
+        );
+        let credential = &github.login.as_ref().unwrap().fido_2_credentials[0];
+        assert_eq!(credential.rp_id.as_deref(), Some("github.com"));
+        // unknown types are written back out as they were
+        assert_eq!(serde_json::to_value(github).unwrap()["type"], 42);
+
+        assert_eq!(list.errors.len(), 1);
+        assert_eq!(list.errors[0].name.as_deref(), Some("database"));
+        assert!(list.errors[0].id.is_some());
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    fs::DirBuilder,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::backend::VaultBackend;
+use super::bwapi::account_kdf;
+use super::bwclient::{Folder, Secret, SecretList, Status};
+use super::bwcrypto::Kdf;
+use super::peer_caller;
+
+/// How long to wait for `bw serve` to start accepting requests.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Backend using a long-lived `bw serve` process and its Vault Management API.
+///
+/// The API has no authentication of its own, so it listens on a unix socket in a directory only
+/// this user can enter rather than on a localhost port.
+pub struct BWServe {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+}
+
+impl BWServe {
+    /// Start `bw serve` on a private unix socket and wait for it to be ready.
+    pub fn start(bin_path: &str) -> anyhow::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("bwfs-serve-{}", Uuid::new_v4().simple()));
+        // fails rather than reusing a directory someone else made
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let socket = dir.join("bw.sock");
+        let mut cmd = Command::new(bin_path);
+        cmd.arg("serve")
+            .arg("--hostname")
+            .arg(format!("unix:{}", socket.display()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null());
+        info!("Starting {:?}", cmd);
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(error) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(error.into());
+            }
+        };
+        let mut serve = Self { child, dir, socket };
+
+        let start = Instant::now();
+        loop {
+            if let Some(status) = serve.child.try_wait()? {
+                anyhow::bail!("bw serve exited during startup with {status}");
+            }
+            match serve.status() {
+                Ok(_) => break,
+                Err(error) if start.elapsed() > STARTUP_TIMEOUT => {
+                    anyhow::bail!("bw serve did not start in time: {error}");
+                }
+                Err(error) => {
+                    debug!(%error, "Waiting for bw serve to start");
+                    std::thread::sleep(Duration::from_millis(200));
+                }
+            }
+        }
+        std::fs::set_permissions(&serve.socket, std::fs::Permissions::from_mode(0o600))?;
+        info!(socket = %serve.socket.display(), "bw serve started");
+        Ok(serve)
+    }
+
+    fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> anyhow::Result<T> {
+        debug!(method, path, "Request");
+        let body = http_request(&self.socket, method, path, body)?;
+        parse_response(&body)
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        self.request("GET", path, None)
+    }
+
+    fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> anyhow::Result<T> {
+        self.request("POST", path, body)
+    }
+}
+
+impl Drop for BWServe {
+    fn drop(&mut self) {
+        if let Err(error) = self.child.kill() {
+            warn!(%error, "Failed to stop bw serve");
+        }
+        let _ = self.child.wait();
+        if let Err(error) = std::fs::remove_dir_all(&self.dir) {
+            warn!(%error, "Failed to remove bw serve socket");
+        }
+    }
+}
+
+/// Send a single HTTP/1.1 request over the unix socket and return the response body.
+///
+/// The status isn't checked, failures still carry a json body with the reason.
+fn http_request(
+    socket: &Path,
+    method: &str,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket)?;
+    // only our own processes can reach the socket, but make sure before sending the password
+    let peer = peer_caller(&stream)?;
+    // SAFETY: getuid has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    anyhow::ensure!(
+        peer.uid == uid,
+        "bw serve socket is owned by uid {}, not {uid}",
+        peer.uid
+    );
+
+    let body = match body {
+        Some(body) => serde_json::to_vec(&body)?,
+        None => Vec::new(),
+    };
+    let mut request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+        body.len()
+    );
+    if !body.is_empty() {
+        request.push_str("Content-Type: application/json\r\n");
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(&body)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    anyhow::ensure!(
+        line.starts_with("HTTP/1."),
+        "Invalid response from bw serve: {line:?}"
+    );
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = Some(value.parse::<usize>()?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16)
+                .with_context(|| format!("Invalid chunk size from bw serve: {line:?}"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = length {
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+    Ok(body)
+}
+
+/// Envelope around every response from `bw serve`.
+#[derive(Debug, serde::Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    message: Option<String>,
+    data: Option<T>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ApiTemplate<T> {
+    template: T,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ApiList<T> {
+    data: Vec<T>,
+}
+
+fn parse_response<T: DeserializeOwned>(body: &[u8]) -> anyhow::Result<T> {
+    let body: ApiResponse<T> = serde_json::from_slice(body)?;
+    match body {
+        ApiResponse {
+            success: true,
+            data: Some(data),
+            ..
+        } => Ok(data),
+        ApiResponse { message, .. } => Err(anyhow::anyhow!(
+            message.unwrap_or_else(|| "bw serve request failed".to_owned())
+        )),
+    }
+}
+
+impl VaultBackend for BWServe {
+    fn status(&self) -> anyhow::Result<Status> {
+        let status: ApiTemplate<Status> = self.get("/status")?;
+        debug!(?status, "Got status");
+        Ok(status.template)
+    }
+
+    fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
+        debug!("Unlocking vault");
+        let _: serde_json::Value =
+            self.post("/unlock", Some(serde_json::json!({ "password": password })))?;
+        Ok(())
+    }
+
+    fn lock(&mut self) {
+        if let Err(error) = self.post::<serde_json::Value>("/lock", None) {
+            warn!(%error, "Failed to lock vault");
+        }
+    }
+
+    fn list_secrets(&self) -> anyhow::Result<SecretList> {
+        let list: ApiList<serde_json::Value> = self.get("/list/object/items")?;
+        Ok(SecretList::parse(list.data))
+    }
+
+    fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
+        let list: ApiList<Folder> = self.get("/list/object/folders")?;
+        Ok(list.data)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        let _: serde_json::Value = self.post("/sync", None)?;
+        Ok(())
+    }
+
+    fn edit(&self, secret: &Secret) -> anyhow::Result<Secret> {
+        self.request(
+            "PUT",
+            &format!("/object/item/{}", secret.id),
+            Some(serde_json::to_value(secret)?),
+        )
+    }
+
+    fn kdf(&self) -> Option<Kdf> {
+        account_kdf(&self.status().ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    /// Answer one request on a socket in `dir` with `response`, returning the socket and the
+    /// thread that gets the request.
+    fn serve_once(
+        dir: &Path,
+        response: &'static str,
+    ) -> (PathBuf, std::thread::JoinHandle<String>) {
+        let socket = dir.join("bw.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let thread = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut length = 0;
+            while !request.ends_with("\r\n\r\n") {
+                let start = request.len();
+                reader.read_line(&mut request).unwrap();
+                if let Some(value) = request[start..].strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(std::str::from_utf8(&body).unwrap());
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (socket, thread)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bwfs-test-{name}-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn requests_over_socket() {
+        let dir = temp_dir("serve");
+        let (socket, thread) = serve_once(
+            &dir,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 42\r\n\r\n{\"success\":true,\"data\":{\"template\":\"ok\"}}\n",
+        );
+        let body = http_request(
+            &socket,
+            "POST",
+            "/unlock",
+            Some(serde_json::json!({ "password": "hunter2" })),
+        )
+        .unwrap();
+        let data: ApiTemplate<String> = parse_response(&body).unwrap();
+        assert_eq!(data.template, "ok");
+        let request = thread.join().unwrap();
+        assert!(request.starts_with("POST /unlock HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"password\":\"hunter2\"}"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn chunked_failure() {
+        let dir = temp_dir("serve-chunked");
+        let (socket, thread) = serve_once(
+            &dir,
+            "HTTP/1.1 400 Bad Request\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n{\"success\":false\r\n1c\r\n,\"message\":\"Invalid master\"}\r\n0\r\n\r\n",
+        );
+        let body = http_request(&socket, "GET", "/status", None).unwrap();
+        let error = parse_response::<serde_json::Value>(&body).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid master");
+        assert!(thread
+            .join()
+            .unwrap()
+            .starts_with("GET /status HTTP/1.1\r\n"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
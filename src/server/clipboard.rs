@@ -0,0 +1,335 @@
+use std::{
+    io::Write,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use tracing::{debug, info, warn};
+
+/// Environment variables a client passes along so the server can reach its session's clipboard.
+pub const CLIPBOARD_ENV: &[&str] = &[
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+    "DISPLAY",
+    "XAUTHORITY",
+];
+
+/// Variables in [`CLIPBOARD_ENV`] holding paths, which must belong to the client.
+const CLIPBOARD_PATHS: &[&str] = &["XDG_RUNTIME_DIR", "XAUTHORITY"];
+
+/// Clipboard of a Wayland or X11 session, used through `wl-copy`/`wl-paste` or `xclip`.
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    env: Vec<(String, String)>,
+    /// Where to find the tools, found through `PATH` when empty.
+    bin_dir: PathBuf,
+}
+
+impl Clipboard {
+    /// Clipboard of the session described by a client's `env`, run by `uid`.
+    ///
+    /// Only the variables in [`CLIPBOARD_ENV`] are used, and paths in them must be owned by `uid`
+    /// so a client can't reach a session other than its own.
+    pub fn new(env: Vec<(String, String)>, uid: u32) -> anyhow::Result<Self> {
+        let mut allowed = Vec::new();
+        for (name, value) in env {
+            if !CLIPBOARD_ENV.contains(&name.as_str()) {
+                warn!(name, "Ignoring clipboard variable");
+                continue;
+            }
+            // an absolute WAYLAND_DISPLAY is the path of the socket itself
+            if CLIPBOARD_PATHS.contains(&name.as_str())
+                || (name == "WAYLAND_DISPLAY" && value.contains('/'))
+            {
+                check_owner(&name, Path::new(&value), uid)?;
+            }
+            allowed.push((name, value));
+        }
+        Ok(Self {
+            env: allowed,
+            bin_dir: PathBuf::new(),
+        })
+    }
+
+    fn wayland(&self) -> bool {
+        self.env.iter().any(|(name, _)| name == "WAYLAND_DISPLAY")
+    }
+
+    fn command(&self, wayland: &[&str], x11: &[&str]) -> Command {
+        let args = if self.wayland() { wayland } else { x11 };
+        let mut cmd = Command::new(self.bin_dir.join(args[0]));
+        cmd.args(&args[1..]).envs(self.env.iter().cloned());
+        cmd
+    }
+
+    /// Get the current contents, empty if there are none.
+    pub fn get(&self) -> anyhow::Result<Vec<u8>> {
+        let mut cmd = self.command(
+            &["wl-paste", "--no-newline"],
+            &["xclip", "-selection", "clipboard", "-o"],
+        );
+        let output = cmd
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("Failed to run {:?}", cmd.get_program()))?;
+        // both fail when there's nothing on the clipboard
+        Ok(if output.status.success() {
+            output.stdout
+        } else {
+            Vec::new()
+        })
+    }
+
+    pub fn set(&self, value: &[u8]) -> anyhow::Result<()> {
+        let mut cmd = self.command(&["wl-copy"], &["xclip", "-selection", "clipboard"]);
+        // the tools fork to serve the clipboard, so keep stdout from holding us up
+        cmd.stdin(Stdio::piped()).stdout(Stdio::null());
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to run {:?}", cmd.get_program()))?;
+        child.stdin.take().unwrap().write_all(value)?;
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("{:?} failed with {status}", cmd.get_program());
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) -> anyhow::Result<()> {
+        if self.wayland() {
+            let status = self.command(&["wl-copy", "--clear"], &[]).status()?;
+            if !status.success() {
+                anyhow::bail!("wl-copy failed with {status}");
+            }
+            Ok(())
+        } else {
+            self.set(b"")
+        }
+    }
+}
+
+/// Check that the path in the variable `name` is absolute and owned by `uid`.
+fn check_owner(name: &str, path: &Path, uid: u32) -> anyhow::Result<()> {
+    if !path.is_absolute() {
+        anyhow::bail!("{name} must be an absolute path");
+    }
+    let owner = std::fs::metadata(path)
+        .with_context(|| format!("{name}: {}", path.display()))?
+        .uid();
+    if owner != uid {
+        warn!(name, path = %path.display(), owner, uid, "Refusing clipboard path of another user");
+        anyhow::bail!("{name} is not owned by you");
+    }
+    Ok(())
+}
+
+/// A secret placed on a clipboard, waiting to be taken off again.
+#[derive(Debug)]
+struct Copied {
+    id: u64,
+    clipboard: Clipboard,
+    secret: Vec<u8>,
+    /// What was on the clipboard before the secret.
+    previous: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct ClipboardTimer {
+    next_id: u64,
+    copied: Option<Copied>,
+}
+
+/// Copies secrets to clipboards and takes them off again after a timeout.
+#[derive(Debug, Clone, Default)]
+pub struct ClipboardTimerRef(pub Arc<Mutex<ClipboardTimer>>);
+
+impl ClipboardTimerRef {
+    /// Put `secret` on `clipboard`, restoring the previous contents after `clear_after`.
+    pub fn copy(
+        &self,
+        clipboard: Clipboard,
+        secret: String,
+        clear_after: Duration,
+    ) -> anyhow::Result<()> {
+        let mut timer = self.0.lock().unwrap();
+        let mut previous = clipboard.get().unwrap_or_default();
+        if let Some(copied) = &timer.copied {
+            if copied.secret == previous {
+                // replacing a secret we copied, so restore what was there before that instead
+                previous = std::mem::take(&mut timer.copied.as_mut().unwrap().previous);
+            }
+        }
+        clipboard.set(secret.as_bytes())?;
+        timer.next_id += 1;
+        let id = timer.next_id;
+        timer.copied = Some(Copied {
+            id,
+            clipboard,
+            secret: secret.into_bytes(),
+            previous,
+        });
+        drop(timer);
+
+        let timer = self.clone();
+        std::thread::Builder::new()
+            .name("clipboard-clear".to_owned())
+            .spawn(move || {
+                std::thread::sleep(clear_after);
+                timer.restore(Some(id));
+            })?;
+        Ok(())
+    }
+
+    /// Restore the clipboard if it still holds the copied secret.
+    ///
+    /// With an `id`, only does so if that is still the latest copy.
+    pub fn restore(&self, id: Option<u64>) {
+        let mut timer = self.0.lock().unwrap();
+        if id.is_some_and(|id| timer.copied.as_ref().is_some_and(|c| c.id != id)) {
+            debug!(id, "Clipboard copy superseded, leaving it");
+            return;
+        }
+        let Some(copied) = timer.copied.take() else {
+            return;
+        };
+        match copied.clipboard.get() {
+            Ok(current) if current == copied.secret => {
+                let res = if copied.previous.is_empty() {
+                    copied.clipboard.clear()
+                } else {
+                    copied.clipboard.set(&copied.previous)
+                };
+                match res {
+                    Ok(()) => info!("Removed secret from clipboard"),
+                    Err(error) => warn!(%error, "Failed to restore clipboard"),
+                }
+            }
+            Ok(_) => debug!("Clipboard changed since copying, leaving it"),
+            Err(error) => warn!(%error, "Failed to read clipboard"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use super::*;
+
+    fn uid() -> u32 {
+        // SAFETY: getuid has no preconditions and can't fail
+        unsafe { libc::getuid() }
+    }
+
+    /// Clipboard backed by a file through fake `wl-copy` and `wl-paste` scripts.
+    fn fake_clipboard(name: &str) -> (Clipboard, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("bwfs-clip-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let clip = dir.join("clip");
+        let clip = clip.display();
+        let scripts = [
+            (
+                "wl-copy",
+                format!("if [ \"$1\" = --clear ]; then rm -f '{clip}'; else cat > '{clip}'; fi"),
+            ),
+            ("wl-paste", format!("cat '{clip}'")),
+        ];
+        for (name, script) in scripts {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let env = vec![("WAYLAND_DISPLAY".to_owned(), "wayland-test".to_owned())];
+        let mut clipboard = Clipboard::new(env, uid()).unwrap();
+        clipboard.bin_dir = dir.clone();
+        (clipboard, dir)
+    }
+
+    #[test]
+    fn only_session_env() {
+        let (_, dir) = fake_clipboard("env");
+        let runtime_dir = ("XDG_RUNTIME_DIR".to_owned(), dir.display().to_string());
+        let env = vec![
+            ("WAYLAND_DISPLAY".to_owned(), "wayland-0".to_owned()),
+            ("PATH".to_owned(), dir.display().to_string()),
+            (
+                "LD_PRELOAD".to_owned(),
+                dir.join("evil.so").display().to_string(),
+            ),
+            runtime_dir.clone(),
+        ];
+        let clipboard = Clipboard::new(env, uid()).unwrap();
+        assert_eq!(
+            clipboard.env,
+            [
+                ("WAYLAND_DISPLAY".to_owned(), "wayland-0".to_owned()),
+                runtime_dir.clone(),
+            ]
+        );
+
+        assert!(Clipboard::new(vec![runtime_dir], uid() + 1).is_err());
+        let relative = ("XAUTHORITY".to_owned(), ".Xauthority".to_owned());
+        assert!(Clipboard::new(vec![relative], uid()).is_err());
+        let socket = (
+            "WAYLAND_DISPLAY".to_owned(),
+            "/nonexistent/wayland-0".to_owned(),
+        );
+        assert!(Clipboard::new(vec![socket], uid()).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    const LONG: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn restores_previous() {
+        let (clipboard, dir) = fake_clipboard("restore");
+        clipboard.set(b"hello").unwrap();
+        let timer = ClipboardTimerRef::default();
+
+        timer.copy(clipboard.clone(), "a".to_owned(), LONG).unwrap();
+        timer.copy(clipboard.clone(), "b".to_owned(), LONG).unwrap();
+        assert_eq!(clipboard.get().unwrap(), b"b");
+        // an older copy's timer is superseded
+        timer.restore(Some(1));
+        assert_eq!(clipboard.get().unwrap(), b"b");
+        timer.restore(Some(2));
+        assert_eq!(clipboard.get().unwrap(), b"hello");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn clears_or_leaves() {
+        let (clipboard, dir) = fake_clipboard("clear");
+        let timer = ClipboardTimerRef::default();
+
+        timer.copy(clipboard.clone(), "a".to_owned(), LONG).unwrap();
+        timer.restore(None);
+        assert_eq!(clipboard.get().unwrap(), b"");
+
+        timer.copy(clipboard.clone(), "a".to_owned(), LONG).unwrap();
+        clipboard.set(b"mine").unwrap();
+        timer.restore(None);
+        assert_eq!(clipboard.get().unwrap(), b"mine");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn timer_expires() {
+        let (clipboard, dir) = fake_clipboard("expire");
+        let timer = ClipboardTimerRef::default();
+        timer
+            .copy(clipboard.clone(), "a".to_owned(), Duration::ZERO)
+            .unwrap();
+        let start = std::time::Instant::now();
+        while clipboard.get().unwrap() == b"a" {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(clipboard.get().unwrap(), b"");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+//! How items are laid out as files below their folder.
+
+use clap::ValueEnum;
+
+use super::bwclient::{LinkedId, Secret, SecretField, SecretFieldType};
+use super::mapfs::sanitize_name;
+
+/// Field of the files holding a whole item as JSON.
+pub const ITEM_JSON: &str = "item.json";
+/// Field of the files holding a whole item as `NAME=value` lines.
+pub const ITEM_ENV: &str = "item.env";
+
+/// Whether files of `field` hold the whole item rather than a single field.
+pub fn is_item_field(field: &str) -> bool {
+    field == ITEM_JSON || field == ITEM_ENV
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// A directory per item with a file per field, e.g. `github/password` and `github/uris/01`,
+    /// and the whole item in `github/item.json` and `github/item.env`.
+    #[default]
+    Nested,
+    /// A file per field next to the other items, e.g. `github.password` and `github.uris.01`.
+    Flat,
+    /// A single `<item>.json` file per item.
+    JsonPerItem,
+    /// A single `<item>.env` file per item, of `NAME=value` lines.
+    EnvFilePerItem,
+}
+
+/// A file an item is shown as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemFile {
+    /// Names from the item's folder down to the file, already sanitized.
+    pub path: Vec<String>,
+    /// The field the file holds, [`ITEM_JSON`] or [`ITEM_ENV`] for the whole item.
+    pub field: String,
+    pub content: String,
+}
+
+impl Layout {
+    /// Path of the item named `name`, relative to its folder.
+    ///
+    /// This is its directory or single file, for the flat layout it's what its files start with.
+    pub fn item_path(self, name: &str) -> String {
+        let name = sanitize_name(name);
+        match self {
+            Layout::Nested | Layout::Flat => name,
+            Layout::JsonPerItem => format!("{name}.json"),
+            Layout::EnvFilePerItem => format!("{name}.env"),
+        }
+    }
+
+    /// Whether each item has an entry of its own, being a directory or a single file.
+    pub fn has_item_entries(self) -> bool {
+        self != Layout::Flat
+    }
+
+    /// The files to show `secret` as.
+    pub fn files(self, secret: &Secret) -> Vec<ItemFile> {
+        let item_path = self.item_path(&secret.name);
+        let whole = |field: &str, content| ItemFile {
+            path: vec![item_path.clone()],
+            field: field.to_owned(),
+            content,
+        };
+        match self {
+            Layout::Nested | Layout::Flat => item_fields(secret)
+                .into_iter()
+                .map(|(field, content)| {
+                    let mut names: Vec<_> = match field.split_once('/') {
+                        Some((dir, rest)) => vec![dir.to_owned(), sanitize_name(rest)],
+                        None => vec![field.clone()],
+                    };
+                    let path = if self == Layout::Nested {
+                        names.insert(0, item_path.clone());
+                        names
+                    } else {
+                        // sanitized names have no dots, so joining with them stays unambiguous
+                        vec![format!("{item_path}.{}", names.join("."))]
+                    };
+                    ItemFile {
+                        path,
+                        field,
+                        content,
+                    }
+                })
+                .chain(
+                    (self == Layout::Nested)
+                        .then(|| {
+                            // sanitized names have no dots, so these can't clash with a field
+                            [(ITEM_JSON, item_json(secret)), (ITEM_ENV, item_env(secret))].map(
+                                |(field, content)| ItemFile {
+                                    path: vec![item_path.clone(), field.to_owned()],
+                                    field: field.to_owned(),
+                                    content,
+                                },
+                            )
+                        })
+                        .into_iter()
+                        .flatten(),
+                )
+                .collect(),
+            Layout::JsonPerItem => vec![whole(ITEM_JSON, item_json(secret))],
+            Layout::EnvFilePerItem => vec![whole(ITEM_ENV, item_env(secret))],
+        }
+    }
+}
+
+/// The fields of `secret` with their values, each field being the path of its file in the nested
+/// layout.
+pub fn item_fields(secret: &Secret) -> Vec<(String, String)> {
+    let mut fields = vec![("type".to_owned(), secret.r#type.to_string())];
+    if let Some(login) = &secret.login {
+        if let Some(username) = &login.username {
+            fields.push(("username".to_owned(), username.clone()));
+        }
+        if let Some(password) = &login.password {
+            fields.push(("password".to_owned(), password.clone()));
+        }
+        if let Some(totp) = &login.totp {
+            fields.push(("totp".to_owned(), totp.clone()));
+        }
+        for (i, uri) in login.uris.iter().flatten().enumerate() {
+            fields.push((format!("uris/{:02}", i + 1), uri.uri.clone()));
+        }
+    }
+    if let Some(card) = &secret.card {
+        let card_fields = [
+            ("cardholder_name", &card.cardholder_name),
+            ("brand", &card.brand),
+            ("number", &card.number),
+            ("exp_month", &card.exp_month),
+            ("exp_year", &card.exp_year),
+            ("code", &card.code),
+        ];
+        for (name, value) in card_fields {
+            if let Some(value) = value {
+                fields.push((format!("card/{name}"), value.clone()));
+            }
+        }
+    }
+    if let Some(ssh_key) = &secret.ssh_key {
+        fields.push(("private_key".to_owned(), ssh_key.private_key.clone()));
+        fields.push(("public_key".to_owned(), ssh_key.public_key.clone()));
+        fields.push(("fingerprint".to_owned(), ssh_key.key_fingerprint.clone()));
+    }
+    if let Some(notes) = &secret.notes {
+        fields.push(("notes".to_owned(), notes.clone()));
+    }
+    for field in secret.fields.iter().flatten() {
+        if let Some(value) = custom_value(secret, field) {
+            fields.push((format!("fields/{}", field.name), value));
+        }
+    }
+    fields.push(("id".to_owned(), secret.id.to_string()));
+    fields
+}
+
+/// The value of a custom field, linked fields taking it from the login or card property they
+/// point at.
+///
+/// Linked fields pointing at properties that aren't shown, like those of identities, are left
+/// out.
+fn custom_value(secret: &Secret, field: &SecretField) -> Option<String> {
+    if field.r#type != SecretFieldType::Linked {
+        return Some(field.value.clone().unwrap_or_default());
+    }
+    let login = secret.login.as_ref();
+    let card = secret.card.as_ref();
+    let value = match field.linked_id? {
+        LinkedId::LoginUsername => login?.username.as_ref(),
+        LinkedId::LoginPassword => login?.password.as_ref(),
+        LinkedId::CardCardholderName => card?.cardholder_name.as_ref(),
+        LinkedId::CardExpMonth => card?.exp_month.as_ref(),
+        LinkedId::CardExpYear => card?.exp_year.as_ref(),
+        LinkedId::CardCode => card?.code.as_ref(),
+        LinkedId::CardBrand => card?.brand.as_ref(),
+        LinkedId::CardNumber => card?.number.as_ref(),
+        LinkedId::Other(_) => None,
+    };
+    value.cloned()
+}
+
+/// Whether the vault hides the value of `field`, as it does for hidden custom fields and those
+/// linked to the password or the card number or code.
+pub fn is_hidden(secret: &Secret, field: &str) -> bool {
+    let Some(name) = field.strip_prefix("fields/") else {
+        return false;
+    };
+    let custom = secret.fields.iter().flatten().find(|f| f.name == name);
+    custom.is_some_and(|f| match f.r#type {
+        SecretFieldType::Hidden => true,
+        SecretFieldType::Linked => matches!(
+            f.linked_id,
+            Some(LinkedId::LoginPassword | LinkedId::CardNumber | LinkedId::CardCode)
+        ),
+        SecretFieldType::Text | SecretFieldType::Boolean | SecretFieldType::Other(_) => false,
+    })
+}
+
+/// The item as JSON, like `bwfs get --json` gives it.
+pub fn item_json(secret: &Secret) -> String {
+    let mut secret = secret.clone();
+    secret.password_history = None;
+    serde_json::to_string_pretty(&secret).unwrap()
+}
+
+/// The item as `NAME=value` lines that can be sourced by a shell.
+///
+/// Names are the uppercased fields, with custom fields as `FIELD_<NAME>`.
+pub fn item_env(secret: &Secret) -> String {
+    item_fields(secret)
+        .into_iter()
+        .map(|(field, value)| {
+            let name = match field.strip_prefix("fields/") {
+                Some(custom) => format!("FIELD_{custom}"),
+                None => field,
+            };
+            format!("{}={}\n", env_name(&name), shell_quote(&value))
+        })
+        .collect()
+}
+
+/// Uppercase `name`, replacing anything not allowed in a variable name with `_`.
+fn env_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// Quote `value` for a POSIX shell, leaving it bare if that's safe.
+fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+,=".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        value.to_owned()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::server::bwclient::SecretList;
+
+    fn github() -> Secret {
+        let items = serde_json::from_str(include_str!("../../tests/fixtures/items.json")).unwrap();
+        SecretList::parse(items).secrets.remove(0)
+    }
+
+    fn paths(layout: Layout) -> Vec<String> {
+        layout
+            .files(&github())
+            .into_iter()
+            .map(|f| f.path.join("/"))
+            .collect()
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(
+            paths(Layout::Nested),
+            [
+                "github/type",
+                "github/username",
+                "github/password",
+                "github/uris/01",
+                "github/notes",
+                "github/fields/pin",
+                "github/id",
+                "github/item.json",
+                "github/item.env"
+            ]
+        );
+        assert_eq!(
+            paths(Layout::Flat),
+            [
+                "github.type",
+                "github.username",
+                "github.password",
+                "github.uris.01",
+                "github.notes",
+                "github.fields.pin",
+                "github.id"
+            ]
+        );
+        assert_eq!(paths(Layout::JsonPerItem), ["github.json"]);
+        let files = Layout::EnvFilePerItem.files(&github());
+        assert_eq!(files[0].path, ["github.env"]);
+        assert_eq!(files[0].field, ITEM_ENV);
+    }
+
+    #[test]
+    fn custom_field_types() {
+        let mut secret = github();
+        let field = |name: &str, r#type, linked_id| SecretField {
+            name: name.to_owned(),
+            value: None,
+            r#type,
+            linked_id,
+        };
+        secret.fields.as_mut().unwrap().extend([
+            SecretField {
+                value: Some("blue".to_owned()),
+                ..field("colour", SecretFieldType::Text, None)
+            },
+            field(
+                "user",
+                SecretFieldType::Linked,
+                Some(LinkedId::LoginUsername),
+            ),
+            field(
+                "pass",
+                SecretFieldType::Linked,
+                Some(LinkedId::LoginPassword),
+            ),
+            // an identity's title, which isn't shown
+            field("other", SecretFieldType::Linked, Some(LinkedId::Other(400))),
+            field("code", SecretFieldType::Linked, Some(LinkedId::CardCode)),
+        ]);
+
+        let fields: BTreeMap<_, _> = item_fields(&secret).into_iter().collect();
+        assert_eq!(fields["fields/colour"], "blue");
+        assert_eq!(fields["fields/user"], "octocat");
+        assert_eq!(fields["fields/pass"], "correct horse battery staple");
+        assert!(!fields.contains_key("fields/other"));
+        // not a card
+        assert!(!fields.contains_key("fields/code"));
+
+        assert!(is_hidden(&secret, "fields/pin"));
+        assert!(is_hidden(&secret, "fields/pass"));
+        assert!(!is_hidden(&secret, "fields/colour"));
+        assert!(!is_hidden(&secret, "fields/user"));
+        assert!(!is_hidden(&secret, "password"));
+
+        let items = serde_json::from_str(include_str!("../../tests/fixtures/items.json")).unwrap();
+        let mut visa = SecretList::parse(items)
+            .secrets
+            .into_iter()
+            .find(|s| s.name == "visa")
+            .unwrap();
+        visa.fields = Some(vec![
+            field("cvv", SecretFieldType::Linked, Some(LinkedId::CardCode)),
+            field(
+                "month",
+                SecretFieldType::Linked,
+                Some(LinkedId::CardExpMonth),
+            ),
+            field(
+                "user",
+                SecretFieldType::Linked,
+                Some(LinkedId::LoginUsername),
+            ),
+        ]);
+        let fields: BTreeMap<_, _> = item_fields(&visa).into_iter().collect();
+        assert_eq!(fields["fields/cvv"], "123");
+        assert_eq!(fields["fields/month"], "12");
+        assert!(!fields.contains_key("fields/user"));
+        assert!(is_hidden(&visa, "fields/cvv"));
+        assert!(!is_hidden(&visa, "fields/month"));
+    }
+
+    #[test]
+    fn env_quoting() {
+        let env = item_env(&github());
+        assert!(env.contains("USERNAME=octocat\n"));
+        assert!(env.contains("PASSWORD='correct horse battery staple'\n"));
+        assert!(env.contains("URIS_01=https://github.com\n"));
+        assert!(env.contains("FIELD_PIN=1234\n"));
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(env_name("2fa key"), "_2FA_KEY");
+    }
+}
@@ -1,31 +1,104 @@
 use fuser::FileAttr;
 use fuser::FileType;
 use fuser::Filesystem;
-use libc::ENOENT;
-use std::collections::BTreeMap;
+use libc::{EACCES, EINVAL, EISDIR, ELOOP, ENODATA, ENOENT, ERANGE};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ffi::OsStr;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
+use time::format_description::well_known::Rfc3339;
 use tracing::debug;
 use tracing::info;
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::server::bwclient::StatusKind;
 
-use super::bwclient::Secret;
-use super::bwclient::BWCLI;
+use super::approval::{Approval, ApprovalPrompt, Approver};
+use super::audit::{AuditEntry, AuditLogRef, AuditOperation};
+use super::backend::{Vault, VaultBackend};
+use super::bwclient::{ItemError, Secret, SecretFieldType, SecretLoginUri, UriMatch};
+use super::layout::{is_hidden, is_item_field, item_fields, Layout, ITEM_ENV, ITEM_JSON};
+use super::policy::Policy;
+use super::rules::AccessRules;
+use super::urimatch::{uri_host, UriMatcher};
 
 #[derive(Clone, Debug)]
 pub struct MapFSRef(pub Arc<Mutex<MapFS>>);
 
 impl MapFSRef {
-    pub fn refresh(&self, cli: &BWCLI) -> anyhow::Result<()> {
-        self.0.lock().unwrap().refresh(cli)
+    pub fn refresh(&self, backend: &dyn VaultBackend) -> anyhow::Result<()> {
+        self.0.lock().unwrap().refresh(backend)
     }
 
     pub fn clear(&self) {
         self.0.lock().unwrap().clear()
     }
+
+    pub fn read_path(&self, caller: &Caller, path: &str) -> Result<String, i32> {
+        self.approved(|fs| fs.read_path(caller, path))
+    }
+
+    pub fn read_item(&self, caller: &Caller, path: &str) -> Result<Secret, i32> {
+        self.approved(|fs| fs.read_item(caller, path))
+    }
+
+    pub fn list_items(&self, caller: &Caller) -> Vec<ItemSummary> {
+        self.0.lock().unwrap().list_items(caller)
+    }
+
+    pub fn match_uri(&self, caller: &Caller, url: &str) -> Vec<String> {
+        self.0.lock().unwrap().match_uri(caller, url)
+    }
+
+    pub fn ssh_keys(&self, caller: &Caller) -> Vec<(String, String)> {
+        self.0.lock().unwrap().ssh_keys(caller)
+    }
+
+    pub fn read_field(&self, caller: &Caller, item_path: &str, field: &str) -> Result<String, i32> {
+        self.approved(|fs| fs.read_field(caller, item_path, field))
+    }
+
+    pub fn modified(&self, caller: &Caller, path: &str) -> Result<SystemTime, i32> {
+        self.0.lock().unwrap().modified(caller, path)
+    }
+
+    /// Run `op`, asking for any approval it needs first.
+    fn approved<T>(&self, mut op: impl FnMut(&mut MapFS) -> Result<T, i32>) -> Result<T, i32> {
+        let prompt = {
+            let mut fs = self.0.lock().unwrap();
+            match fs.attempt(&mut op) {
+                Ok(res) => return res,
+                Err(prompt) => prompt,
+            }
+        };
+        self.after_approval(prompt, op)
+    }
+
+    /// Ask for the approval `op` needs with the filesystem unlocked, so nothing else waits on
+    /// the user, then run `op` again if given.
+    ///
+    /// Running it again checks everything afresh, as the tree may have been locked or refreshed
+    /// in the meantime.
+    fn after_approval<T>(
+        &self,
+        mut prompt: Box<ApprovalPrompt>,
+        mut op: impl FnMut(&mut MapFS) -> Result<T, i32>,
+    ) -> Result<T, i32> {
+        let approved = prompt.run();
+        let mut fs = self.0.lock().unwrap();
+        if !approved {
+            debug!("access not approved");
+            return Err(EACCES);
+        }
+        fs.approver.remember(&prompt);
+        match fs.attempt(&mut op) {
+            Ok(res) => res,
+            // approvals only hold for the same executable, which may have changed since
+            Err(_) => Err(EACCES),
+        }
+    }
 }
 
 impl Filesystem for MapFSRef {
@@ -183,7 +256,26 @@ impl Filesystem for MapFSRef {
     }
 
     fn open(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
-        self.0.lock().unwrap().open(req, ino, flags, reply)
+        info!(ino, flags, "open");
+        let caller = Caller::from(req);
+        let open = move |fs: &mut MapFS| fs.open_file(&caller, ino, flags);
+        let attempt = self.0.lock().unwrap().attempt(open);
+        let prompt = match attempt {
+            Ok(Ok(fh)) => return reply.opened(fh, 0),
+            Ok(Err(error)) => return reply.error(error),
+            Err(prompt) => prompt,
+        };
+        // answer from another thread so other requests aren't held up waiting for the user
+        let fs = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("approval".to_owned())
+            .spawn(move || match fs.after_approval(prompt, open) {
+                Ok(fh) => reply.opened(fh, 0),
+                Err(error) => reply.error(error),
+            });
+        if let Err(error) = spawned {
+            warn!(%error, "Failed to spawn approval thread");
+        }
     }
 
     fn read(
@@ -512,6 +604,9 @@ impl Filesystem for MapFSRef {
     }
 }
 
+/// Most symlinks followed resolving one path, as in Linux.
+const MAX_LINKS_FOLLOWED: usize = 40;
+
 #[derive(Debug)]
 pub enum FSEntry {
     Dir {
@@ -521,13 +616,61 @@ pub enum FSEntry {
     },
     File {
         content: String,
+        source: FieldRef,
         ctime: SystemTime,
         mtime: SystemTime,
     },
+    /// A symlink into the folder tree, `target` being relative to the link's directory.
+    Link {
+        target: String,
+        ctime: SystemTime,
+        mtime: SystemTime,
+    },
+}
+
+/// The item and field a file holds the value of.
+#[derive(Debug, Clone)]
+pub struct FieldRef {
+    pub item: Uuid,
+    /// Path of the file relative to its item directory, e.g. `uris/01`.
+    pub field: String,
+    /// Whether the vault hides the value, see [`is_hidden`].
+    pub hidden: bool,
+}
+
+/// What a picker may show about an item, leaving out anything secret.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ItemSummary {
+    /// Path of the item relative to the root, see [`Layout::item_path`].
+    pub path: String,
+    pub username: Option<String>,
+    pub uris: Vec<String>,
+    /// Paths of the item's files relative to the root, by the field they hold, e.g. `password`
+    /// or `uris/01`.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// The process a request comes from, over FUSE or the control socket.
+#[derive(Debug, Clone, Copy)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+}
+
+impl From<&fuser::Request<'_>> for Caller {
+    fn from(req: &fuser::Request<'_>) -> Self {
+        Self {
+            uid: req.uid(),
+            gid: req.gid(),
+            pid: req.pid(),
+        }
+    }
 }
 
 impl FSEntry {
-    fn attrs(&self, ino: u64, perm: u16, uid: u32, gid: u32) -> FileAttr {
+    fn attrs(&self, ino: u64, policy: &Policy) -> FileAttr {
+        let perms = policy.permissions(self.kind(), self.field(), self.hidden());
         FileAttr {
             ino,
             size: self.size(),
@@ -537,10 +680,10 @@ impl FSEntry {
             ctime: self.ctime(),
             crtime: SystemTime::now(),
             kind: self.kind(),
-            perm,
+            perm: perms.mode,
             nlink: 1,
-            uid,
-            gid,
+            uid: perms.uid,
+            gid: perms.gid,
             rdev: 1,
             blksize: 1024,
             flags: 0,
@@ -551,13 +694,29 @@ impl FSEntry {
         match self {
             FSEntry::Dir { .. } => FileType::Directory,
             FSEntry::File { .. } => FileType::RegularFile,
+            FSEntry::Link { .. } => FileType::Symlink,
         }
     }
 
     fn size(&self) -> u64 {
         match self {
             FSEntry::Dir { .. } => 0,
-            FSEntry::File { content, .. } => content.as_bytes().len() as u64,
+            FSEntry::File { content, .. } => content.len() as u64,
+            FSEntry::Link { target, .. } => target.len() as u64,
+        }
+    }
+
+    fn field(&self) -> &str {
+        match self {
+            FSEntry::Dir { .. } | FSEntry::Link { .. } => "",
+            FSEntry::File { source, .. } => &source.field,
+        }
+    }
+
+    fn hidden(&self) -> bool {
+        match self {
+            FSEntry::Dir { .. } | FSEntry::Link { .. } => false,
+            FSEntry::File { source, .. } => source.hidden,
         }
     }
 
@@ -565,6 +724,7 @@ impl FSEntry {
         match self {
             FSEntry::Dir { ctime, .. } => *ctime,
             FSEntry::File { ctime, .. } => *ctime,
+            FSEntry::Link { ctime, .. } => *ctime,
         }
     }
 
@@ -572,6 +732,7 @@ impl FSEntry {
         match self {
             FSEntry::Dir { mtime, .. } => *mtime,
             FSEntry::File { mtime, .. } => *mtime,
+            FSEntry::Link { mtime, .. } => *mtime,
         }
     }
 }
@@ -582,23 +743,58 @@ pub struct MapFS {
     inode_map: BTreeMap<u64, FSEntry>,
     handles: BTreeMap<u64, u64>,
     generation: u64,
-    permissions: u16,
-    uid: u32,
-    gid: u32,
+    policy: Policy,
     folders: Vec<String>,
+    audit: AuditLogRef,
+    approver: Approver,
+    /// Names of items that need approval before being read.
+    reprompt: BTreeMap<Uuid, String>,
+    rules: Option<AccessRules>,
+    /// Path of each item relative to the root, see [`Layout::item_path`].
+    item_paths: BTreeMap<Uuid, String>,
+    /// Path of each item relative to the root in the nested layout, which access rules match.
+    rule_paths: BTreeMap<Uuid, String>,
+    /// Paths of the files of each item relative to the root, by the field they hold.
+    field_paths: BTreeMap<Uuid, BTreeMap<String, String>>,
+    /// Item each item directory is for, by inode.
+    item_dirs: BTreeMap<u64, Uuid>,
+    /// Items as listed by the backend.
+    items: BTreeMap<Uuid, Secret>,
+    matcher: UriMatcher,
+    layout: Layout,
+    /// Prompt for the approval the last check found missing, see [`MapFS::attempt`].
+    pending_approval: Option<Box<ApprovalPrompt>>,
 }
 
 impl MapFS {
-    pub fn new(uid: u32, gid: u32, permissions: u16, folders: Vec<String>) -> Self {
+    pub fn new(
+        policy: Policy,
+        folders: Vec<String>,
+        audit: AuditLogRef,
+        approver: Approver,
+        rules: Option<AccessRules>,
+        matcher: UriMatcher,
+        layout: Layout,
+    ) -> Self {
         let mut s = Self {
             name_map: BTreeMap::new(),
             inode_map: BTreeMap::new(),
             handles: BTreeMap::new(),
             generation: 1,
-            permissions,
-            uid,
-            gid,
+            policy,
             folders,
+            audit,
+            approver,
+            reprompt: BTreeMap::new(),
+            rules,
+            item_paths: BTreeMap::new(),
+            rule_paths: BTreeMap::new(),
+            field_paths: BTreeMap::new(),
+            item_dirs: BTreeMap::new(),
+            items: BTreeMap::new(),
+            matcher,
+            layout,
+            pending_approval: None,
         };
         s.inode_map.insert(
             1,
@@ -622,7 +818,17 @@ impl MapFS {
         ctime: SystemTime,
         mtime: SystemTime,
     ) -> u64 {
-        let name = sanitize_name(&name);
+        self.insert_dir(parent, sanitize_name(&name), ctime, mtime)
+    }
+
+    /// Add a directory with `name` as is, which may be one `sanitize_name` wouldn't produce.
+    fn insert_dir(
+        &mut self,
+        parent: u64,
+        name: String,
+        ctime: SystemTime,
+        mtime: SystemTime,
+    ) -> u64 {
         let inode = self.next_id();
         if let Some(FSEntry::Dir { children, .. }) = self.inode_map.get_mut(&parent) {
             children.insert(name.clone(), inode);
@@ -643,11 +849,24 @@ impl MapFS {
         &mut self,
         parent: u64,
         name: String,
+        source: FieldRef,
+        value: String,
+        ctime: SystemTime,
+        mtime: SystemTime,
+    ) -> u64 {
+        self.insert_file(parent, sanitize_name(&name), source, value, ctime, mtime)
+    }
+
+    /// Add a file with `name` as is, which may be one `sanitize_name` wouldn't produce.
+    fn insert_file(
+        &mut self,
+        parent: u64,
+        name: String,
+        source: FieldRef,
         value: String,
         ctime: SystemTime,
         mtime: SystemTime,
     ) -> u64 {
-        let name = sanitize_name(&name);
         let inode = self.next_id();
         if let Some(FSEntry::Dir { children, .. }) = self.inode_map.get_mut(&parent) {
             children.insert(name.clone(), inode);
@@ -657,6 +876,32 @@ impl MapFS {
             inode,
             FSEntry::File {
                 content: value,
+                source,
+                ctime,
+                mtime,
+            },
+        );
+        inode
+    }
+
+    /// Add a symlink named `name`, as is, pointing at `target`.
+    fn add_link(
+        &mut self,
+        parent: u64,
+        name: String,
+        target: String,
+        ctime: SystemTime,
+        mtime: SystemTime,
+    ) -> u64 {
+        let inode = self.next_id();
+        if let Some(FSEntry::Dir { children, .. }) = self.inode_map.get_mut(&parent) {
+            children.insert(name.clone(), inode);
+        }
+        self.name_map.insert((parent, name), inode);
+        self.inode_map.insert(
+            inode,
+            FSEntry::Link {
+                target,
                 ctime,
                 mtime,
             },
@@ -664,6 +909,27 @@ impl MapFS {
         inode
     }
 
+    /// List the items that couldn't be parsed in a `.errors` file at the root.
+    ///
+    /// Item names never start with a dot, so it can't clash with a folder.
+    fn add_errors(&mut self, errors: &[ItemError]) {
+        let content: String = errors
+            .iter()
+            .map(|e| {
+                let id = e.id.map_or_else(|| "-".to_owned(), |id| id.to_string());
+                let name = e.name.as_deref().unwrap_or("-");
+                format!("{id} {name}: {}\n", e.error)
+            })
+            .collect();
+        let source = FieldRef {
+            item: Uuid::nil(),
+            field: ".errors".to_owned(),
+            hidden: false,
+        };
+        let now = SystemTime::now();
+        self.insert_file(1, ".errors".to_owned(), source, content, now, now);
+    }
+
     pub fn register_fh(&mut self, ino: u64) -> u64 {
         let new_fh = self.handles.values().max().copied().unwrap_or_default() + 1;
         *self.handles.entry(ino).or_insert(new_fh)
@@ -673,43 +939,465 @@ impl MapFS {
         self.name_map.get(&(parent, name)).copied()
     }
 
+    pub fn entry(&self, ino: u64) -> Option<&FSEntry> {
+        self.inode_map.get(&ino)
+    }
+
+    /// Check that `caller` may access `ino` with the given `mask`.
+    fn check_access(&self, caller: &Caller, ino: u64, mask: i32) -> Result<(), i32> {
+        let entry = self.inode_map.get(&ino).ok_or(ENOENT)?;
+        let perms = self
+            .policy
+            .permissions(entry.kind(), entry.field(), entry.hidden());
+        let gids = caller_groups(caller);
+        if perms.allows(caller.uid, &gids, mask) {
+            Ok(())
+        } else {
+            debug!(ino, caller.uid, caller.pid, mask, "denied access");
+            Err(EACCES)
+        }
+    }
+
+    /// Check that the requesting process is allowed to read the field of the item `ino` holds.
+    ///
+    /// Rules match the item's path and fields as in the nested layout, so they hold whatever the
+    /// layout. Files holding the whole item are covered by the rules of each of its fields.
+    fn check_rules(&mut self, caller: &Caller, ino: u64) -> Result<(), i32> {
+        let Some(rules) = &mut self.rules else {
+            return Ok(());
+        };
+        let Some(FSEntry::File { source, .. }) = self.inode_map.get(&ino) else {
+            return Ok(());
+        };
+        let Some(item_path) = self.rule_paths.get(&source.item) else {
+            return Ok(());
+        };
+        let fields = if is_item_field(&source.field) {
+            self.items
+                .get(&source.item)
+                .map(|secret| item_fields(secret).into_iter().map(|(f, _)| f).collect())
+                .unwrap_or_default()
+        } else {
+            vec![source.field.clone()]
+        };
+        if rules.allows(item_path, &fields, caller.pid) {
+            Ok(())
+        } else {
+            warn!(item_path, caller.uid, caller.pid, "access denied by rules");
+            self.audit(caller, ino, AuditOperation::Denied);
+            Err(EACCES)
+        }
+    }
+
+    /// Check for approval if `ino` belongs to an item that requires reprompting.
+    ///
+    /// If the user needs asking this fails, leaving the prompt to be run by [`MapFS::attempt`].
+    fn check_approval(&mut self, caller: &Caller, ino: u64) -> Result<(), i32> {
+        let Some(FSEntry::File { source, .. }) = self.inode_map.get(&ino) else {
+            return Ok(());
+        };
+        let Some(name) = self.reprompt.get(&source.item) else {
+            return Ok(());
+        };
+        match self
+            .approver
+            .check(source.item, name, &source.field, caller.uid, caller.pid)
+        {
+            Approval::Approved => Ok(()),
+            Approval::Denied => {
+                debug!(ino, caller.uid, caller.pid, "access not approved");
+                Err(EACCES)
+            }
+            Approval::Prompt(prompt) => {
+                self.pending_approval = Some(prompt);
+                Err(EACCES)
+            }
+        }
+    }
+
+    /// Run `op`, giving the prompt to run instead if it needs an approval from the user.
+    fn attempt<T>(
+        &mut self,
+        op: impl FnOnce(&mut Self) -> Result<T, i32>,
+    ) -> Result<Result<T, i32>, Box<ApprovalPrompt>> {
+        self.pending_approval = None;
+        let res = op(self);
+        match (res, self.pending_approval.take()) {
+            (Err(_), Some(prompt)) => Err(prompt),
+            (res, _) => Ok(res),
+        }
+    }
+
+    /// Open the file `ino` for `caller`, giving the new file handle.
+    fn open_file(&mut self, caller: &Caller, ino: u64, flags: i32) -> Result<u64, i32> {
+        let mask = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => libc::R_OK,
+            libc::O_WRONLY => libc::W_OK,
+            _ => libc::R_OK | libc::W_OK,
+        };
+        self.check_access(caller, ino, mask)?;
+        self.check_rules(caller, ino)?;
+        self.check_approval(caller, ino)?;
+        self.audit(caller, ino, AuditOperation::Open);
+        Ok(self.register_fh(ino))
+    }
+
+    /// Record an access to a file in the audit log.
+    fn audit(&self, caller: &Caller, ino: u64, operation: AuditOperation) {
+        if let Some(FSEntry::File { source, .. }) = self.inode_map.get(&ino) {
+            self.audit.record(AuditEntry::new(
+                operation,
+                source.item,
+                source.field.clone(),
+                caller.uid,
+                caller.pid,
+            ));
+        }
+    }
+
+    /// Find the inode at `path`, relative to the root, checking `caller` may traverse to it.
+    fn resolve(&self, caller: &Caller, path: &str) -> Result<u64, i32> {
+        self.resolve_canonical(caller, path).map(|(ino, _)| ino)
+    }
+
+    /// Like [`MapFS::resolve`], also giving the path of the inode with any symlinks followed.
+    fn resolve_canonical(&self, caller: &Caller, path: &str) -> Result<(u64, String), i32> {
+        let mut pending: VecDeque<String> = path
+            .split('/')
+            .filter(|n| !n.is_empty())
+            .map(str::to_owned)
+            .collect();
+        // the directories walked through so far, with their names
+        let mut walked: Vec<(u64, String)> = Vec::new();
+        let mut links_followed = 0;
+        while let Some(name) = pending.pop_front() {
+            let ino = walked.last().map_or(1, |(ino, _)| *ino);
+            match name.as_str() {
+                "." => continue,
+                ".." => {
+                    walked.pop();
+                    continue;
+                }
+                _ => {}
+            }
+            self.check_access(caller, ino, libc::X_OK)?;
+            let child = self.find(ino, name.clone()).ok_or(ENOENT)?;
+            if let Some(FSEntry::Link { target, .. }) = self.inode_map.get(&child) {
+                links_followed += 1;
+                if links_followed > MAX_LINKS_FOLLOWED {
+                    return Err(ELOOP);
+                }
+                for part in target.rsplit('/').filter(|n| !n.is_empty()) {
+                    pending.push_front(part.to_owned());
+                }
+                continue;
+            }
+            walked.push((child, name));
+        }
+        let ino = walked.last().map_or(1, |(ino, _)| *ino);
+        let path = walked
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>()
+            .join("/");
+        Ok((ino, path))
+    }
+
+    /// Read the file at `path`, relative to the root, on behalf of `caller`.
+    ///
+    /// Goes through the same checks as opening the file in the mount would.
+    pub fn read_path(&mut self, caller: &Caller, path: &str) -> Result<String, i32> {
+        let ino = self.resolve(caller, path)?;
+        match self.inode_map.get(&ino) {
+            Some(FSEntry::File { .. }) => {}
+            Some(FSEntry::Dir { .. }) => return Err(EISDIR),
+            Some(FSEntry::Link { .. }) | None => return Err(ENOENT),
+        }
+        self.check_access(caller, ino, libc::R_OK)?;
+        self.check_rules(caller, ino)?;
+        self.check_approval(caller, ino)?;
+        self.audit(caller, ino, AuditOperation::Read);
+        match self.inode_map.get(&ino) {
+            Some(FSEntry::File { content, .. }) => Ok(content.clone()),
+            _ => Err(ENOENT),
+        }
+    }
+
+    /// Get the whole item at or containing `path` on behalf of `caller`, without its history.
+    ///
+    /// The caller must be able to read every file of the item.
+    pub fn read_item(&mut self, caller: &Caller, path: &str) -> Result<Secret, i32> {
+        let (ino, path) = self.resolve_canonical(caller, path)?;
+        let item = match self.inode_map.get(&ino) {
+            Some(FSEntry::File { source, .. }) => source.item,
+            Some(FSEntry::Dir { .. }) => {
+                *self
+                    .item_paths
+                    .iter()
+                    .find(|(_, p)| **p == path)
+                    .ok_or(EISDIR)?
+                    .0
+            }
+            Some(FSEntry::Link { .. }) | None => return Err(ENOENT),
+        };
+        let files: Vec<u64> = self
+            .inode_map
+            .iter()
+            .filter(|(_, e)| matches!(e, FSEntry::File { source, .. } if source.item == item))
+            .map(|(ino, _)| *ino)
+            .collect();
+        for ino in &files {
+            self.check_access(caller, *ino, libc::R_OK)?;
+        }
+        if let Some(ino) = files.first() {
+            self.check_rules(caller, *ino)?;
+            self.check_approval(caller, *ino)?;
+        }
+        for ino in &files {
+            self.audit(caller, *ino, AuditOperation::Read);
+        }
+        let mut secret = self.items.get(&item).cloned().ok_or(ENOENT)?;
+        secret.password_history = None;
+        Ok(secret)
+    }
+
+    /// Summarise the items `caller` can see, with usernames and URIs they may read if not secret.
+    pub fn list_items(&self, caller: &Caller) -> Vec<ItemSummary> {
+        let mut items = Vec::new();
+        for (item, path) in &self.item_paths {
+            let files: BTreeMap<_, _> = self.field_paths[item]
+                .iter()
+                .filter_map(|(field, path)| Some((field, (path, self.resolve(caller, path).ok()?))))
+                .collect();
+            if files.is_empty() {
+                continue;
+            }
+            let readable = |field: &str| {
+                files
+                    .get(&field.to_owned())
+                    .filter(|(_, ino)| {
+                        !self.policy.is_secret(field)
+                            && self.check_access(caller, *ino, libc::R_OK).is_ok()
+                    })
+                    .and_then(|(_, ino)| match self.inode_map.get(ino) {
+                        Some(FSEntry::File {
+                            content, source, ..
+                        }) if !source.hidden => Some(content.clone()),
+                        _ => None,
+                    })
+            };
+            let uris = files
+                .keys()
+                .filter(|f| f.starts_with("uris/"))
+                .filter_map(|f| readable(f))
+                .collect();
+            items.push(ItemSummary {
+                path: path.clone(),
+                username: readable("username"),
+                uris,
+                fields: files
+                    .iter()
+                    .map(|(field, (path, _))| ((*field).clone(), (*path).clone()))
+                    .collect(),
+            });
+        }
+        items
+    }
+
+    /// Path of the file holding `field` of `item`, which may hold the whole item.
+    fn field_file(&self, item: &Uuid, field: &str) -> Option<&String> {
+        let paths = self.field_paths.get(item)?;
+        paths
+            .get(field)
+            .or_else(|| paths.get(ITEM_JSON))
+            .or_else(|| paths.get(ITEM_ENV))
+    }
+
+    /// Whether `caller` can get to any of the files of `item`.
+    fn item_visible(&self, caller: &Caller, item: &Uuid) -> bool {
+        self.field_paths
+            .get(item)
+            .is_some_and(|paths| paths.values().any(|p| self.resolve(caller, p).is_ok()))
+    }
+
+    /// Read `field` of the item at `item_path`, relative to the root, on behalf of `caller`.
+    ///
+    /// In layouts with a file per item this reads that file, giving only the field's value.
+    pub fn read_field(
+        &mut self,
+        caller: &Caller,
+        item_path: &str,
+        field: &str,
+    ) -> Result<String, i32> {
+        let item = *self
+            .item_paths
+            .iter()
+            .find(|(_, p)| *p == item_path)
+            .ok_or(ENOENT)?
+            .0;
+        let path = self.field_file(&item, field).ok_or(ENOENT)?.clone();
+        let content = self.read_path(caller, &path)?;
+        if self.field_paths[&item].contains_key(field) {
+            return Ok(content);
+        }
+        let secret = self.items.get(&item).ok_or(ENOENT)?;
+        item_fields(secret)
+            .into_iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, value)| value)
+            .ok_or(ENOENT)
+    }
+
+    /// Paths of the login items with a URI matching `url` that `caller` can get into.
+    pub fn match_uri(&self, caller: &Caller, url: &str) -> Vec<String> {
+        self.items
+            .values()
+            .filter(|secret| {
+                let uris = secret.login.as_ref().and_then(|l| l.uris.as_ref());
+                uris.is_some_and(|uris| uris.iter().any(|uri| self.matcher.matches(uri, url)))
+            })
+            .filter(|secret| self.item_visible(caller, &secret.id))
+            .filter_map(|secret| self.item_paths.get(&secret.id))
+            .cloned()
+            .collect()
+    }
+
+    /// Paths and public keys of the SSH key items whose private key `caller` may read.
+    pub fn ssh_keys(&self, caller: &Caller) -> Vec<(String, String)> {
+        self.items
+            .values()
+            .filter_map(|secret| {
+                let public_key = &secret.ssh_key.as_ref()?.public_key;
+                let path = self.item_paths.get(&secret.id)?;
+                let private_key = self.field_file(&secret.id, "private_key")?;
+                let ino = self.resolve(caller, private_key).ok()?;
+                self.check_access(caller, ino, libc::R_OK).ok()?;
+                Some((path.clone(), public_key.clone()))
+            })
+            .collect()
+    }
+
+    /// Extended attributes of the entry `ino`, describing the item it's for without its secrets.
+    fn xattrs(&self, ino: u64) -> Vec<(String, String)> {
+        let (item, field) = match self.inode_map.get(&ino) {
+            Some(FSEntry::File { source, .. }) => (source.item, Some(source.field.as_str())),
+            Some(FSEntry::Dir { .. }) => match self.item_dirs.get(&ino) {
+                Some(item) => (*item, None),
+                None => return Vec::new(),
+            },
+            Some(FSEntry::Link { .. }) | None => return Vec::new(),
+        };
+        let Some(secret) = self.items.get(&item) else {
+            return Vec::new();
+        };
+        let folder = self
+            .item_paths
+            .get(&item)
+            .and_then(|path| path.rsplit_once('/'))
+            .map_or("", |(folder, _)| folder);
+        let mut attrs = vec![
+            ("id", secret.id.to_string()),
+            ("type", secret.r#type.to_string()),
+            ("folder", folder.to_owned()),
+            (
+                "revision",
+                secret.revision_date.format(&Rfc3339).unwrap_or_default(),
+            ),
+            ("favorite", secret.favorite.to_string()),
+        ];
+        if let Some(name) = field.and_then(|f| f.strip_prefix("fields/")) {
+            let custom = secret.fields.iter().flatten().find(|f| f.name == name);
+            if let Some(custom) = custom {
+                attrs.push(("field_type", field_type_name(custom.r#type)));
+            }
+        }
+        if let Some(n) = field.and_then(|f| f.strip_prefix("uris/")) {
+            let uri = n.parse::<usize>().ok().and_then(|n| {
+                let uris = secret.login.as_ref()?.uris.as_ref()?;
+                uris.get(n.checked_sub(1)?)
+            });
+            if let Some(r#match) = uri.and_then(|uri| uri.r#match) {
+                attrs.push(("uri_match", uri_match_name(r#match)));
+            }
+        }
+        attrs
+            .into_iter()
+            .map(|(name, value)| (format!("{XATTR_PREFIX}{name}"), value))
+            .collect()
+    }
+
+    /// Modification time of the entry at `path`, the revision date of the item for its files.
+    pub fn modified(&self, caller: &Caller, path: &str) -> Result<SystemTime, i32> {
+        let ino = self.resolve(caller, path)?;
+        self.inode_map.get(&ino).map(FSEntry::mtime).ok_or(ENOENT)
+    }
+
+    /// Empty the tree and forget all approvals, as when locking.
     pub fn clear(&mut self) {
-        let root_inode = self
+        self.reset();
+        self.approver.clear();
+    }
+
+    /// Empty the tree, keeping the approvals given so far.
+    fn reset(&mut self) {
+        let mut root_inode = self
             .inode_map
             .remove(&1)
             .expect("Root inode should always exist");
+        if let FSEntry::Dir { children, .. } = &mut root_inode {
+            // inodes get reused so stale children would point at the wrong entries
+            children.clear();
+        }
+        let approver = std::mem::replace(
+            &mut self.approver,
+            Approver::new(None, Duration::ZERO, Duration::ZERO),
+        );
         *self = Self {
             name_map: Default::default(),
             inode_map: Default::default(),
             handles: Default::default(),
             generation: self.generation + 1,
-            permissions: self.permissions,
-            uid: self.uid,
-            gid: self.gid,
+            policy: self.policy.clone(),
             folders: std::mem::take(&mut self.folders),
+            audit: self.audit.clone(),
+            approver,
+            reprompt: Default::default(),
+            rules: self.rules.take(),
+            item_paths: Default::default(),
+            rule_paths: Default::default(),
+            field_paths: Default::default(),
+            item_dirs: Default::default(),
+            items: Default::default(),
+            matcher: std::mem::take(&mut self.matcher),
+            layout: self.layout,
+            pending_approval: None,
         };
         self.inode_map.insert(1, root_inode);
     }
 
-    pub fn refresh(&mut self, cli: &BWCLI) -> anyhow::Result<()> {
-        if cli
+    pub fn refresh(&mut self, backend: &dyn VaultBackend) -> anyhow::Result<()> {
+        if !backend
             .status()
-            .ok()
-            .map_or(true, |s| s.status != StatusKind::Unlocked)
+            .is_ok_and(|s| s.status == StatusKind::Unlocked)
         {
-            anyhow::bail!("BWCLI is locked");
+            anyhow::bail!("Vault is locked");
         }
 
-        self.clear();
-        println!("Listing folders");
-        let mut folders = cli.list_folders()?;
+        self.reset();
+        info!("Vault is unlocked, listing folders and secrets");
+        let Vault {
+            mut folders,
+            mut secrets,
+            errors,
+        } = backend.list_vault()?;
+        if !errors.is_empty() {
+            self.add_errors(&errors);
+        }
         if !self.folders.is_empty() {
             folders.retain(|f| self.folders.iter().any(|af| f.name.starts_with(af)));
         }
-        println!("Vault is unlocked, listing secrets");
-        let mut secrets = cli.list_secrets()?;
 
-        println!("Filtering secrets");
+        info!("Filtering secrets");
         let original_len = secrets.len();
         if !folders.is_empty() {
             let folder_ids = folders.iter().map(|f| f.id.unwrap_or_default()).collect();
@@ -743,70 +1431,295 @@ impl MapFS {
                 name = parts[keep].to_owned();
             }
             let inode = self.add_dir(parent, name, SystemTime::now(), SystemTime::now());
-            folders_map.insert(folder.id.unwrap_or_default(), inode);
+            let path = parts
+                .iter()
+                .map(|p| sanitize_name(p))
+                .collect::<Vec<_>>()
+                .join("/");
+            folders_map.insert(folder.id.unwrap_or_default(), (inode, path));
         }
 
         for secret in secrets {
-            let folder_id = folders_map
+            let (folder_id, folder_path) = folders_map
                 .get(&secret.folder_id.unwrap_or_default())
                 .unwrap();
+            let folder_id = *folder_id;
+            let item_path = format!("{folder_path}/{}", self.layout.item_path(&secret.name));
+            self.item_paths.insert(secret.id, item_path);
+            let rule_path = format!("{folder_path}/{}", Layout::Nested.item_path(&secret.name));
+            self.rule_paths.insert(secret.id, rule_path);
             let ctime = SystemTime::from(secret.creation_date);
             let mtime = SystemTime::from(secret.revision_date);
-            let parent = self.add_dir(*folder_id, secret.name, ctime, mtime);
-            self.add_file(
-                parent,
-                "type".to_owned(),
-                secret.r#type.to_string(),
-                ctime,
-                mtime,
-            );
-            if let Some(login) = secret.login {
-                if let Some(username) = login.username {
-                    self.add_file(parent, "username".to_owned(), username, ctime, mtime);
-                }
-                if let Some(password) = login.password {
-                    self.add_file(parent, "password".to_owned(), password, ctime, mtime);
-                }
-                if let Some(uris) = login.uris {
-                    if !uris.is_empty() {
-                        let uris_dir = self.add_dir(parent, "uris".to_owned(), ctime, mtime);
-                        for (i, uri) in uris.into_iter().enumerate() {
-                            self.add_file(uris_dir, format!("{:02}", i + 1), uri.uri, ctime, mtime);
+            if secret.reprompt != 0 {
+                self.reprompt.insert(secret.id, secret.name.clone());
+            }
+            let mut field_paths = BTreeMap::new();
+            // directories made for this item, so items of the same name don't share one
+            let mut dirs = BTreeMap::new();
+            for file in self.layout.files(&secret) {
+                let (name, dir_names) = file.path.split_last().unwrap();
+                let mut parent = folder_id;
+                for depth in 0..dir_names.len() {
+                    let names = dir_names[..=depth].to_vec();
+                    parent = match dirs.get(&names) {
+                        Some(dir) => *dir,
+                        None => {
+                            let dir = self.insert_dir(parent, names[depth].clone(), ctime, mtime);
+                            dirs.insert(names, dir);
+                            dir
                         }
-                    }
+                    };
                 }
+                let source = FieldRef {
+                    item: secret.id,
+                    hidden: is_hidden(&secret, &file.field),
+                    field: file.field.clone(),
+                };
+                self.insert_file(parent, name.clone(), source, file.content, ctime, mtime);
+                field_paths.insert(file.field, format!("{folder_path}/{}", file.path.join("/")));
+            }
+            self.field_paths.insert(secret.id, field_paths);
+            if let Some(dir) = dirs.get(&vec![self.layout.item_path(&secret.name)]) {
+                self.item_dirs.insert(*dir, secret.id);
             }
-            if let Some(notes) = secret.notes {
-                self.add_file(parent, "notes".to_owned(), notes, ctime, mtime);
+            self.items.insert(secret.id, secret);
+        }
+        let items = &self.items;
+        self.approver.retain(|item| items.contains_key(item));
+        self.add_views();
+        Ok(())
+    }
+
+    /// Add the directories at the root giving other ways to the items, as symlinks into the
+    /// folder tree.
+    fn add_views(&mut self) {
+        if !self.layout.has_item_entries() {
+            return;
+        }
+        let mut items: Vec<_> = self
+            .items
+            .values()
+            .filter_map(|secret| Some(ViewItem::new(self.item_paths.get(&secret.id)?, secret)))
+            .collect();
+        if items.is_empty() {
+            return;
+        }
+        items.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let favorites: Vec<_> = items.iter().filter(|item| item.favorite).collect();
+        if let Some(dir) = self.add_view(FAVORITES_VIEW, !favorites.is_empty()) {
+            for item in favorites {
+                self.link_item(dir, &item.name, item, 1);
             }
-            if let Some(fields) = secret.fields {
-                if !fields.is_empty() {
-                    let fields_dir = self.add_dir(parent, "fields".to_owned(), ctime, mtime);
-                    for field in fields {
-                        self.add_file(fields_dir, field.name, field.value, ctime, mtime);
+        }
+
+        if let Some(view) = self.add_view(TYPE_VIEW, true) {
+            for item in &items {
+                let dir = match self.find(view, sanitize_name(&item.r#type)) {
+                    Some(dir) => dir,
+                    None => {
+                        let now = SystemTime::now();
+                        self.add_dir(view, item.r#type.clone(), now, now)
                     }
-                }
+                };
+                self.link_item(dir, &item.name, item, 2);
             }
-            self.add_file(parent, "id".to_owned(), secret.id.to_string(), ctime, mtime);
         }
-        Ok(())
+
+        if let Some(dir) = self.add_view(ID_VIEW, true) {
+            for item in &items {
+                self.link_item(dir, &item.id.to_string(), item, 1);
+            }
+        }
+
+        if let Some(dir) = self.add_view(RECENT_VIEW, true) {
+            let mut recent: Vec<_> = items.iter().collect();
+            // newest first, numbered so listings keep that order
+            recent.sort_by_key(|item| std::cmp::Reverse(item.revision));
+            for (i, item) in recent.into_iter().take(RECENT_COUNT).enumerate() {
+                self.link_item(dir, &format!("{:02} {}", i + 1, item.name), item, 1);
+            }
+        }
+
+        self.add_host_view();
+    }
+
+    /// Add the directory `name` at the root for a view, if it's `wanted` and no folder has the
+    /// same name.
+    fn add_view(&mut self, name: &str, wanted: bool) -> Option<u64> {
+        if !wanted {
+            return None;
+        }
+        if self.find(1, name.to_owned()).is_some() {
+            warn!("A folder is named {name}, leaving out that view");
+            return None;
+        }
+        let now = SystemTime::now();
+        Some(self.insert_dir(1, name.to_owned(), now, now))
+    }
+
+    /// Link to `item` from `dir` as `name`, which should already be sanitized, `depth` directories
+    /// below the root. The link is numbered if `name` is taken.
+    fn link_item(&mut self, dir: u64, name: &str, item: &ViewItem, depth: usize) {
+        let name = name.to_owned();
+        let mut link_name = name.clone();
+        let mut n = 1;
+        while self.find(dir, link_name.clone()).is_some() {
+            n += 1;
+            link_name = format!("{name} {n}");
+        }
+        let target = format!("{}{}", "../".repeat(depth), item.path);
+        self.add_link(dir, link_name, target, item.revision, item.revision);
     }
+
+    /// Add `by-host/<hostname>/` directories linking to the login items matching each host.
+    ///
+    /// The hosts are those of the login URIs, and an item matches a host if one of its URIs is
+    /// for that host or matches `https://<host>/`.
+    fn add_host_view(&mut self) {
+        let logins: Vec<_> = self
+            .items
+            .values()
+            .filter_map(|secret| {
+                let uris = secret.login.as_ref()?.uris.as_ref()?;
+                let path = self.item_paths.get(&secret.id)?;
+                Some((ViewItem::new(path, secret), uris))
+            })
+            .collect();
+        let host = |uri: &SecretLoginUri| match uri.r#match {
+            // not URLs
+            Some(UriMatch::RegularExpression | UriMatch::Never) => None,
+            _ => uri_host(&uri.uri),
+        };
+        let hosts: BTreeSet<_> = logins
+            .iter()
+            .flat_map(|(_, uris)| uris.iter().filter_map(host))
+            .collect();
+
+        let mut links = Vec::new();
+        for hostname in hosts {
+            let url = format!("https://{hostname}/");
+            let mut matching: Vec<_> = logins
+                .iter()
+                .filter(|(_, uris)| {
+                    uris.iter().any(|uri| {
+                        host(uri).as_ref() == Some(&hostname) || self.matcher.matches(uri, &url)
+                    })
+                })
+                .map(|(item, _)| item.clone())
+                .collect();
+            matching.sort_by(|a, b| a.path.cmp(&b.path));
+            if !matching.is_empty() {
+                links.push((hostname, matching));
+            }
+        }
+        let Some(view) = self.add_view(HOST_VIEW, !links.is_empty()) else {
+            return;
+        };
+        for (hostname, matching) in links {
+            let now = SystemTime::now();
+            let dir = self.insert_dir(view, hostname, now, now);
+            for item in &matching {
+                self.link_item(dir, &item.name, item, 2);
+            }
+        }
+    }
+}
+
+/// What the views need to know of an item to link to it.
+#[derive(Debug, Clone)]
+struct ViewItem {
+    /// Path of the item, relative to the root.
+    path: String,
+    /// Name of the item's entry in its folder.
+    name: String,
+    id: Uuid,
+    r#type: String,
+    favorite: bool,
+    revision: SystemTime,
 }
 
+impl ViewItem {
+    fn new(path: &str, secret: &Secret) -> Self {
+        Self {
+            path: path.to_owned(),
+            name: path.rsplit('/').next().unwrap_or(path).to_owned(),
+            id: secret.id,
+            r#type: secret.r#type.to_string(),
+            favorite: secret.favorite,
+            revision: SystemTime::from(secret.revision_date),
+        }
+    }
+}
+
+/// Namespace of the extended attributes describing items.
+const XATTR_PREFIX: &str = "user.bwfs.";
+
+fn field_type_name(r#type: SecretFieldType) -> String {
+    match r#type {
+        SecretFieldType::Text => "text".to_owned(),
+        SecretFieldType::Hidden => "hidden".to_owned(),
+        SecretFieldType::Boolean => "boolean".to_owned(),
+        SecretFieldType::Linked => "linked".to_owned(),
+        SecretFieldType::Other(other) => other.to_string(),
+    }
+}
+
+fn uri_match_name(r#match: UriMatch) -> String {
+    match r#match {
+        UriMatch::Domain => "domain".to_owned(),
+        UriMatch::Host => "host".to_owned(),
+        UriMatch::StartsWith => "starts_with".to_owned(),
+        UriMatch::Exact => "exact".to_owned(),
+        UriMatch::RegularExpression => "regular_expression".to_owned(),
+        UriMatch::Never => "never".to_owned(),
+        UriMatch::Other(other) => other.to_string(),
+    }
+}
+
+/// Reply with `value` if it fits in `size`, or with its size when asked for that with 0.
+fn reply_xattr(value: &[u8], size: u32, reply: fuser::ReplyXattr) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() <= size as usize {
+        reply.data(value);
+    } else {
+        reply.error(ERANGE);
+    }
+}
+
+/// Name of the directory at the root linking to favorite items.
+const FAVORITES_VIEW: &str = "favorites";
+/// Name of the directory at the root grouping items by type.
+const TYPE_VIEW: &str = "by-type";
+/// Name of the directory at the root linking to items by their id.
+const ID_VIEW: &str = "by-id";
+/// Name of the directory at the root linking to the most recently changed items.
+const RECENT_VIEW: &str = "recent";
+/// Name of the directory at the root grouping login items by host.
+const HOST_VIEW: &str = "by-host";
+
+/// Number of items linked from the recent view.
+const RECENT_COUNT: usize = 20;
+
 impl Filesystem for MapFS {
     fn lookup(
         &mut self,
-        _req: &fuser::Request<'_>,
+        req: &fuser::Request<'_>,
         parent: u64,
         name: &std::ffi::OsStr,
         reply: fuser::ReplyEntry,
     ) {
         let name = name.to_str().unwrap();
         info!(parent, name, "lookup");
+        if let Err(error) = self.check_access(&req.into(), parent, libc::X_OK) {
+            reply.error(error);
+            return;
+        }
         if let Some(ino) = self.find(parent, name.to_owned()) {
             if let Some(entry) = self.inode_map.get(&ino) {
                 debug!(name, "looked up secret");
-                let attrs = entry.attrs(ino, self.permissions, self.uid, self.gid);
+                let attrs = entry.attrs(ino, &self.policy);
                 reply.entry(&Duration::ZERO, &attrs, self.generation)
             } else {
                 reply.error(ENOENT);
@@ -817,21 +1730,18 @@ impl Filesystem for MapFS {
         }
     }
 
-    fn opendir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: u64,
-        flags: i32,
-        reply: fuser::ReplyOpen,
-    ) {
+    fn opendir(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
         info!(ino, flags, "opendir");
-        if self.inode_map.contains_key(&ino) {
-            debug!(ino, "Found dir");
-            let fh = self.register_fh(ino);
-            reply.opened(fh, 0)
-        } else {
-            debug!(ino, "Failed to find dir");
-            reply.error(ENOENT)
+        match self.check_access(&req.into(), ino, libc::R_OK) {
+            Ok(()) => {
+                debug!(ino, "Found dir");
+                let fh = self.register_fh(ino);
+                reply.opened(fh, 0)
+            }
+            Err(error) => {
+                debug!(ino, error, "Failed to open dir");
+                reply.error(error)
+            }
         }
     }
 
@@ -839,7 +1749,7 @@ impl Filesystem for MapFS {
         info!(ino, "getattr");
         if let Some(entry) = self.inode_map.get(&ino) {
             debug!(ino, "Found entry");
-            let attrs = entry.attrs(ino, self.permissions, self.uid, self.gid);
+            let attrs = entry.attrs(ino, &self.policy);
             reply.attr(&Duration::ZERO, &attrs);
         } else {
             debug!(ino, "Failed to find entry");
@@ -877,20 +1787,78 @@ impl Filesystem for MapFS {
         }
     }
 
-    fn open(&mut self, _req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+    fn open(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
         info!(ino, flags, "open");
-        if self.inode_map.contains_key(&ino) {
-            let fh = self.register_fh(ino);
-            reply.opened(fh, 0);
-        } else {
-            reply.error(ENOENT);
+        // approvals can't be asked for here, see `MapFSRef::open`
+        self.pending_approval = None;
+        match self.open_file(&req.into(), ino, flags) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(error) => reply.error(error),
         }
     }
 
-    fn read(
+    fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        info!(ino, "readlink");
+        match self.inode_map.get(&ino) {
+            Some(FSEntry::Link { target, .. }) => reply.data(target.as_bytes()),
+            Some(_) => reply.error(EINVAL),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getxattr(
         &mut self,
         _req: &fuser::Request<'_>,
         ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        info!(ino, ?name, size, "getxattr");
+        if !self.inode_map.contains_key(&ino) {
+            return reply.error(ENOENT);
+        }
+        match self
+            .xattrs(ino)
+            .into_iter()
+            .find(|(n, _)| OsStr::new(n) == name)
+        {
+            Some((_, value)) => reply_xattr(value.as_bytes(), size, reply),
+            None => reply.error(ENODATA),
+        }
+    }
+
+    fn listxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        info!(ino, size, "listxattr");
+        if !self.inode_map.contains_key(&ino) {
+            return reply.error(ENOENT);
+        }
+        let mut names = Vec::new();
+        for (name, _) in self.xattrs(ino) {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        reply_xattr(&names, size, reply);
+    }
+
+    fn access(&mut self, req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        info!(ino, mask, "access");
+        match self.check_access(&req.into(), ino, mask) {
+            Ok(()) => reply.ok(),
+            Err(error) => reply.error(error),
+        }
+    }
+
+    fn read(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
         fh: u64,
         offset: i64,
         size: u32,
@@ -900,6 +1868,7 @@ impl Filesystem for MapFS {
     ) {
         info!(ino, fh, offset, size, "read");
         if let Some(FSEntry::File { content, .. }) = self.inode_map.get(&ino) {
+            self.audit(&req.into(), ino, AuditOperation::Read);
             reply.data(content.as_bytes());
         } else {
             reply.error(ENOENT);
@@ -907,7 +1876,22 @@ impl Filesystem for MapFS {
     }
 }
 
-fn sanitize_name(name: &str) -> String {
+/// The primary and supplementary groups of the process making the request.
+fn caller_groups(caller: &Caller) -> Vec<u32> {
+    let mut gids = vec![caller.gid];
+    if let Ok(status) = std::fs::read_to_string(format!("/proc/{}/status", caller.pid)) {
+        if let Some(groups) = status.lines().find_map(|l| l.strip_prefix("Groups:")) {
+            gids.extend(
+                groups
+                    .split_whitespace()
+                    .filter_map(|g| g.parse::<u32>().ok()),
+            );
+        }
+    }
+    gids
+}
+
+pub(super) fn sanitize_name(name: &str) -> String {
     pub const PROHIBITED_PATH_CHARS: &[char] =
         &['/', '\\', '?', '%', '*', ':', '|', '"', '<', '>', '.'];
     name.replace(PROHIBITED_PATH_CHARS, "")
@@ -916,3 +1900,649 @@ fn sanitize_name(name: &str) -> String {
 fn filter_folders(folder_ids: Vec<Uuid>, secrets: &mut Vec<Secret>) {
     secrets.retain(|s| folder_ids.contains(&s.folder_id.unwrap_or_default()))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::server::audit::{AuditLog, AuditSink};
+    use crate::server::policy::DEFAULT_SECRET_FIELDS;
+    use crate::server::testing::{ScriptedBackend, PASSWORD};
+
+    fn mapfs(folders: &[&str]) -> MapFS {
+        let policy = Policy {
+            uid: 1000,
+            gid: 1000,
+            dir_mode: 0o550,
+            file_mode: 0o440,
+            secret_mode: 0o400,
+            secret_fields: vec!["password".to_owned()],
+            rules: Vec::new(),
+        };
+        MapFS::new(
+            policy,
+            folders.iter().map(|f| (*f).to_owned()).collect(),
+            AuditLogRef(Arc::new(Mutex::new(AuditLog::new(AuditSink::None)))),
+            Approver::new(None, Duration::ZERO, Duration::ZERO),
+            None,
+            UriMatcher::default(),
+            Layout::default(),
+        )
+    }
+
+    fn lookup(fs: &MapFS, path: &str) -> Option<u64> {
+        path.split('/')
+            .try_fold(1, |parent, name| fs.find(parent, name.to_owned()))
+    }
+
+    fn read(fs: &MapFS, path: &str) -> Option<String> {
+        match fs.inode_map.get(&lookup(fs, path)?)? {
+            FSEntry::File { content, .. } => Some(content.clone()),
+            FSEntry::Dir { .. } | FSEntry::Link { .. } => None,
+        }
+    }
+
+    fn children(fs: &MapFS, ino: u64) -> Vec<String> {
+        match fs.inode_map.get(&ino) {
+            Some(FSEntry::Dir { children, .. }) => children.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn refresh_builds_tree() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+
+        assert_eq!(
+            children(&fs, 1),
+            [
+                "No Folder",
+                "by-host",
+                "by-id",
+                "by-type",
+                "favorites",
+                "prod",
+                "recent",
+                "web"
+            ]
+        );
+        assert_eq!(
+            children(&fs, lookup(&fs, "web/github").unwrap()),
+            [
+                "fields",
+                "id",
+                "item.env",
+                "item.json",
+                "notes",
+                "password",
+                "type",
+                "uris",
+                "username"
+            ]
+        );
+        assert_eq!(read(&fs, "web/github/username").unwrap(), "octocat");
+        assert_eq!(
+            read(&fs, "web/github/password").unwrap(),
+            "correct horse battery staple"
+        );
+        assert_eq!(
+            read(&fs, "web/github/uris/01").unwrap(),
+            "https://github.com"
+        );
+        assert_eq!(read(&fs, "web/github/fields/pin").unwrap(), "1234");
+        assert_eq!(
+            read(&fs, "prod/eu/database/fields/user").unwrap(),
+            "postgres"
+        );
+        assert_eq!(read(&fs, "web/github/type").unwrap(), "Login");
+        assert_eq!(
+            read(&fs, "web/github/id").unwrap(),
+            "0b8d6c1a-5e3f-4a2b-9c7d-8e9f0a1b2c3d"
+        );
+        assert_eq!(read(&fs, "prod/eu/database/password").unwrap(), "hunter2");
+        assert_eq!(read(&fs, "No Folder/wifi/notes").unwrap(), "guest network");
+        assert!(lookup(&fs, "prod/eu/database/uris").is_none());
+    }
+
+    #[test]
+    fn views_link_into_tree() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let target = |path: &str| match &fs.inode_map[&lookup(&fs, path).unwrap()] {
+            FSEntry::Link { target, .. } => target.clone(),
+            _ => panic!("{path} isn't a link"),
+        };
+
+        assert_eq!(children(&fs, lookup(&fs, "favorites").unwrap()), ["github"]);
+        assert_eq!(target("favorites/github"), "../web/github");
+        assert_eq!(
+            children(&fs, lookup(&fs, "by-type").unwrap()),
+            ["Card", "Login", "SSH key", "Secure note"]
+        );
+        assert_eq!(
+            children(&fs, lookup(&fs, "by-type/Login").unwrap()),
+            ["database", "github"]
+        );
+        assert_eq!(target("by-type/Login/database"), "../../prod/eu/database");
+        assert_eq!(
+            target("by-id/2daf8e3c-7a5b-4c4d-9e9f-0a1b2c3d4e5f"),
+            "../No Folder/wifi"
+        );
+        assert_eq!(
+            children(&fs, lookup(&fs, "recent").unwrap()),
+            [
+                "01 database",
+                "02 deploy",
+                "03 github",
+                "04 wifi",
+                "05 visa"
+            ]
+        );
+
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        assert_eq!(
+            fs.read_path(&owner, "favorites/github/username").unwrap(),
+            "octocat"
+        );
+        assert_eq!(
+            fs.read_path(&owner, "recent/01 database/password").unwrap(),
+            "hunter2"
+        );
+    }
+
+    #[test]
+    fn item_files_match_fields() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+
+        let item: serde_json::Value =
+            serde_json::from_str(&read(&fs, "web/github/item.json").unwrap()).unwrap();
+        assert_eq!(item["login"]["password"], "correct horse battery staple");
+        assert_eq!(item["fields"][0]["value"], "1234");
+        assert!(item["passwordHistory"].is_null());
+        assert_eq!(
+            read(&fs, "web/github/item.env").unwrap(),
+            "TYPE=Login\n\
+             USERNAME=octocat\n\
+             PASSWORD='correct horse battery staple'\n\
+             URIS_01=https://github.com\n\
+             NOTES='recovery codes are in the safe'\n\
+             FIELD_PIN=1234\n\
+             ID=0b8d6c1a-5e3f-4a2b-9c7d-8e9f0a1b2c3d\n"
+        );
+        // both hold the password, so are as protected as its file
+        for path in ["web/github/item.json", "web/github/item.env"] {
+            let ino = lookup(&fs, path).unwrap();
+            assert_eq!(fs.inode_map[&ino].attrs(ino, &fs.policy).perm, 0o400);
+        }
+    }
+
+    #[test]
+    fn hidden_fields_are_secret() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let mode = |path: &str| {
+            let ino = lookup(&fs, path).unwrap();
+            fs.inode_map[&ino].attrs(ino, &fs.policy).perm
+        };
+
+        assert_eq!(mode("web/github/fields/pin"), 0o400);
+        assert_eq!(mode("web/github/password"), 0o400);
+        assert_eq!(mode("web/github/username"), 0o440);
+    }
+
+    #[test]
+    fn default_secret_fields_have_files() {
+        let mut fs = mapfs(&[]);
+        fs.policy.secret_fields = DEFAULT_SECRET_FIELDS
+            .split(',')
+            .map(str::to_owned)
+            .collect();
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let mode = |path: &str| {
+            let ino = lookup(&fs, path).unwrap();
+            fs.inode_map[&ino].attrs(ino, &fs.policy).perm
+        };
+
+        assert_eq!(mode("prod/eu/database/password"), 0o400);
+        assert_eq!(
+            read(&fs, "prod/eu/database/totp").unwrap(),
+            "JBSWY3DPEHPK3PXP"
+        );
+        assert_eq!(mode("prod/eu/database/totp"), 0o400);
+        assert_eq!(read(&fs, "No Folder/visa/card/code").unwrap(), "123");
+        assert_eq!(mode("No Folder/visa/card/code"), 0o400);
+        assert_eq!(mode("No Folder/visa/card/brand"), 0o440);
+        assert_eq!(mode("web/deploy/private_key"), 0o400);
+    }
+
+    #[test]
+    fn xattrs_describe_items() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let xattrs = |path: &str| -> BTreeMap<String, String> {
+            fs.xattrs(lookup(&fs, path).unwrap())
+                .into_iter()
+                .map(|(name, value)| (name.strip_prefix("user.bwfs.").unwrap().to_owned(), value))
+                .collect()
+        };
+
+        let item = xattrs("web/github");
+        assert_eq!(item["id"], "0b8d6c1a-5e3f-4a2b-9c7d-8e9f0a1b2c3d");
+        assert_eq!(item["type"], "Login");
+        assert_eq!(item["folder"], "web");
+        assert_eq!(item["revision"], "2024-02-01T10:00:00Z");
+        assert_eq!(item["favorite"], "true");
+        assert!(!item.contains_key("field_type"));
+        assert_eq!(xattrs("web/github/password"), item);
+        assert_eq!(xattrs("web/github/fields/pin")["field_type"], "hidden");
+        assert_eq!(xattrs("web/github/uris/01")["uri_match"], "domain");
+        assert_eq!(xattrs("prod/eu/database")["folder"], "prod/eu");
+        // folders and views aren't items
+        assert!(xattrs("web").is_empty());
+        assert!(xattrs("favorites/github").is_empty());
+    }
+
+    #[test]
+    fn other_layouts() {
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        let mut fs = mapfs(&[]);
+        fs.layout = Layout::Flat;
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        assert_eq!(
+            read(&fs, "web/github.uris.01").unwrap(),
+            "https://github.com"
+        );
+        assert_eq!(read(&fs, "web/github.fields.pin").unwrap(), "1234");
+        assert!(lookup(&fs, "web/github").is_none());
+        // there's nothing for the views to link to
+        assert!(lookup(&fs, "by-id").is_none());
+        assert_eq!(
+            fs.read_field(&owner, "web/github", "password").unwrap(),
+            "correct horse battery staple"
+        );
+
+        fs.layout = Layout::JsonPerItem;
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        assert_eq!(
+            children(&fs, lookup(&fs, "web").unwrap()),
+            ["deploy.json", "github.json"]
+        );
+        let json: Secret = serde_json::from_str(&read(&fs, "web/github.json").unwrap()).unwrap();
+        assert_eq!(json.name, "github");
+        // holds the password, so it's as protected as the password file
+        let ino = lookup(&fs, "web/github.json").unwrap();
+        assert_eq!(fs.inode_map[&ino].attrs(ino, &fs.policy).perm, 0o400);
+        assert_eq!(
+            fs.read_field(&owner, "web/github.json", "password")
+                .unwrap(),
+            "correct horse battery staple"
+        );
+        assert_eq!(
+            fs.match_uri(&owner, "https://github.com/"),
+            ["web/github.json"]
+        );
+        let link = lookup(&fs, "by-id/0b8d6c1a-5e3f-4a2b-9c7d-8e9f0a1b2c3d").unwrap();
+        assert!(
+            matches!(&fs.inode_map[&link], FSEntry::Link { target, .. } if target == "../web/github.json")
+        );
+
+        fs.layout = Layout::EnvFilePerItem;
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let env = read(&fs, "prod/eu/database.env").unwrap();
+        assert!(env.contains("USERNAME=postgres\nPASSWORD=hunter2\n"));
+    }
+
+    /// A filesystem asking for approval with a script that approves after `delay`, counting how
+    /// often it's run in the file it returns.
+    fn approving_mapfs(name: &str, delay: &str) -> (MapFSRef, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("bwfs-approve-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("approve");
+        let count = dir.join("count");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\nsleep {delay}\necho >> {}\n", count.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut fs = mapfs(&[]);
+        fs.approver = Approver::new(
+            Some(script.display().to_string()),
+            Duration::from_secs(60),
+            Duration::from_secs(10),
+        );
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        (MapFSRef(Arc::new(Mutex::new(fs))), dir)
+    }
+
+    fn prompts(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join("count")).map_or(0, |count| count.lines().count())
+    }
+
+    #[test]
+    fn approvals_outlive_refresh() {
+        let (fs, dir) = approving_mapfs("refresh", "0");
+        let backend = ScriptedBackend::unlocked();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+
+        assert_eq!(
+            fs.read_path(&owner, "No Folder/visa/card/code").unwrap(),
+            "123"
+        );
+        assert_eq!(prompts(&dir), 1);
+        fs.read_path(&owner, "No Folder/visa/card/number").unwrap();
+        fs.refresh(&backend).unwrap();
+        fs.read_path(&owner, "No Folder/visa/card/code").unwrap();
+        assert_eq!(prompts(&dir), 1);
+        // items without reprompt never ask
+        fs.read_path(&owner, "web/github/password").unwrap();
+        assert_eq!(prompts(&dir), 1);
+
+        // locking forgets them
+        fs.clear();
+        fs.refresh(&backend).unwrap();
+        fs.read_path(&owner, "No Folder/visa/card/code").unwrap();
+        assert_eq!(prompts(&dir), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prompts_without_holding_lock() {
+        let (fs, dir) = approving_mapfs("lock", "1");
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+
+        let reader = {
+            let fs = fs.clone();
+            std::thread::spawn(move || fs.read_path(&owner, "No Folder/visa/card/code"))
+        };
+        std::thread::sleep(Duration::from_millis(200));
+        let start = std::time::Instant::now();
+        fs.clear();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        // approved, but the vault was locked while asking
+        assert_eq!(reader.join().unwrap(), Err(ENOENT));
+        assert_eq!(prompts(&dir), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rules_hold_across_layouts() {
+        let file = std::env::temp_dir().join(format!("bwfs-rules-{}.json", std::process::id()));
+        std::fs::write(
+            &file,
+            r#"[{ "path": "web/github/password", "exe": ["/nonexistent/deploy"] }]"#,
+        )
+        .unwrap();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        let mut fs = mapfs(&[]);
+        fs.rules = Some(AccessRules::load(file.clone()).unwrap());
+
+        for (layout, denied, allowed) in [
+            (
+                Layout::Nested,
+                vec!["web/github/password", "web/github/item.json"],
+                "web/github/username",
+            ),
+            (
+                Layout::Flat,
+                vec!["web/github.password"],
+                "web/github.username",
+            ),
+            (
+                Layout::JsonPerItem,
+                vec!["web/github.json"],
+                "web/deploy.json",
+            ),
+            (
+                Layout::EnvFilePerItem,
+                vec!["web/github.env"],
+                "web/deploy.env",
+            ),
+        ] {
+            fs.layout = layout;
+            fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+            for path in denied {
+                assert_eq!(fs.read_path(&owner, path), Err(EACCES), "{path}");
+            }
+            assert!(fs.read_path(&owner, allowed).is_ok(), "{allowed}");
+        }
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn refresh_filters_folders() {
+        let mut fs = mapfs(&["prod"]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+
+        assert_eq!(children(&fs, 1), ["by-id", "by-type", "prod", "recent"]);
+        assert_eq!(read(&fs, "prod/eu/database/username").unwrap(), "postgres");
+        assert!(lookup(&fs, "web").is_none());
+    }
+
+    #[test]
+    fn refresh_requires_unlock() {
+        let mut fs = mapfs(&[]);
+        let mut backend = ScriptedBackend::new();
+        assert!(fs.refresh(&backend).is_err());
+        assert!(children(&fs, 1).is_empty());
+
+        backend.unlock(PASSWORD).unwrap();
+        fs.refresh(&backend).unwrap();
+        assert!(!children(&fs, 1).is_empty());
+    }
+
+    #[test]
+    fn clear_empties_tree() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let generation = fs.generation;
+        fs.clear();
+
+        assert!(children(&fs, 1).is_empty());
+        assert_eq!(fs.inode_map.len(), 1);
+        assert!(fs.item_paths.is_empty());
+        assert_eq!(fs.generation, generation + 1);
+    }
+
+    #[test]
+    fn refresh_replaces_tree() {
+        let mut fs = mapfs(&[]);
+        let backend = ScriptedBackend::unlocked();
+        fs.refresh(&backend).unwrap();
+        let entries = fs.inode_map.len();
+        fs.refresh(&backend).unwrap();
+        assert_eq!(fs.inode_map.len(), entries);
+    }
+
+    #[test]
+    fn file_permissions_follow_policy() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let attrs = |path| {
+            let ino = lookup(&fs, path).unwrap();
+            fs.inode_map[&ino].attrs(ino, &fs.policy)
+        };
+
+        assert_eq!(attrs("web/github").perm, 0o550);
+        assert_eq!(attrs("web/github/username").perm, 0o440);
+        assert_eq!(attrs("web/github/password").perm, 0o400);
+        assert_eq!(attrs("web/github/password").size, 28);
+    }
+
+    #[test]
+    fn read_path_checks_access() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        let other = Caller { uid: 2000, ..owner };
+
+        assert_eq!(
+            fs.read_path(&owner, "web/github/password").unwrap(),
+            "correct horse battery staple"
+        );
+        assert_eq!(
+            fs.read_path(&owner, "/web/github/username/").unwrap(),
+            "octocat"
+        );
+        assert_eq!(fs.read_path(&owner, "web/github"), Err(EISDIR));
+        assert_eq!(fs.read_path(&owner, "web/missing"), Err(ENOENT));
+        assert_eq!(fs.read_path(&other, "web/github/password"), Err(EACCES));
+        assert_eq!(fs.audit.tail(10).len(), 2);
+    }
+
+    #[test]
+    fn modified_is_revision_date() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+
+        let modified = fs.modified(&owner, "web/github/password").unwrap();
+        let github = ScriptedBackend::unlocked()
+            .list_secrets()
+            .unwrap()
+            .secrets
+            .remove(0);
+        assert_eq!(modified, SystemTime::from(github.revision_date));
+        assert_eq!(fs.modified(&owner, "web/missing"), Err(ENOENT));
+    }
+
+    #[test]
+    fn read_item_needs_every_field() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        let group = Caller { uid: 2000, ..owner };
+
+        let item = fs.read_item(&owner, "web/github").unwrap();
+        assert_eq!(item.name, "github");
+        assert!(item.password_history.is_none());
+        let item = fs.read_item(&owner, "web/github/uris/01").unwrap();
+        assert_eq!(item.name, "github");
+        assert_eq!(fs.read_item(&owner, "web").unwrap_err(), EISDIR);
+        // the group can read the username but not the password
+        assert_eq!(fs.read_item(&group, "web/github").unwrap_err(), EACCES);
+    }
+
+    #[test]
+    fn list_items_leaves_out_secrets() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+
+        let items = fs.list_items(&owner);
+        let github = items.iter().find(|i| i.path == "web/github").unwrap();
+        assert_eq!(
+            github,
+            &ItemSummary {
+                path: "web/github".to_owned(),
+                username: Some("octocat".to_owned()),
+                uris: vec!["https://github.com".to_owned()],
+                fields: [
+                    "fields/pin",
+                    "id",
+                    "item.env",
+                    "item.json",
+                    "notes",
+                    "password",
+                    "type",
+                    "uris/01",
+                    "username"
+                ]
+                .map(|f| (f.to_owned(), format!("web/github/{f}")))
+                .into(),
+            }
+        );
+        assert_eq!(items.len(), 5);
+        let json = serde_json::to_string(&items).unwrap();
+        assert!(!json.contains("correct horse battery staple"));
+        assert!(!json.contains("hunter2"));
+
+        // other users can't get into the folders at all
+        let other = Caller {
+            uid: 2000,
+            gid: 2000,
+            ..owner
+        };
+        assert!(fs.list_items(&other).is_empty());
+    }
+
+    #[test]
+    fn match_uri_finds_logins() {
+        let mut fs = mapfs(&[]);
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+
+        assert_eq!(
+            fs.match_uri(&owner, "https://gist.github.com/octocat/repo.git"),
+            ["web/github"]
+        );
+        assert!(fs.match_uri(&owner, "https://gitlab.com/").is_empty());
+
+        assert_eq!(
+            children(&fs, lookup(&fs, "by-host").unwrap()),
+            ["github.com"]
+        );
+        let link = lookup(&fs, "by-host/github.com/github").unwrap();
+        assert!(
+            matches!(&fs.inode_map[&link], FSEntry::Link { target, .. } if target == "../../web/github")
+        );
+        assert_eq!(
+            fs.read_path(&owner, "by-host/github.com/github/username")
+                .unwrap(),
+            "octocat"
+        );
+        let other = Caller {
+            uid: 2000,
+            gid: 2000,
+            ..owner
+        };
+        assert!(fs
+            .match_uri(&other, "https://github.com/octocat/repo.git")
+            .is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+
+use time::OffsetDateTime;
+use tracing::{debug, info, warn};
+
+use super::backend::{Vault, VaultBackend};
+use super::bwclient::{Folder, Secret, SecretList, Status, StatusKind};
+use super::bwcrypto::{self, Kdf, SymmetricKey};
+use crate::client::write_atomic;
+
+/// Key derivation used when the account's can't be looked up and there is no snapshot yet,
+/// matching Bitwarden's default.
+const DEFAULT_KDF: Kdf = Kdf::Pbkdf2 {
+    iterations: 600_000,
+};
+
+/// Snapshot of the vault as stored on disk, with the contents encrypted.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Snapshot {
+    email: String,
+    kdf: Kdf,
+    #[serde(with = "time::serde::rfc3339")]
+    created: OffsetDateTime,
+    /// Encrypted JSON of the [`Vault`].
+    vault: String,
+}
+
+impl Snapshot {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Write the snapshot readable only by the owner, replacing any existing one atomically.
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        write_atomic(path, &serde_json::to_vec(self)?, 0o600)
+    }
+}
+
+/// Derive the key for a snapshot, separate from the keys Bitwarden derives from the master key.
+fn snapshot_key(password: &str, email: &str, kdf: Kdf) -> anyhow::Result<SymmetricKey> {
+    let master_key = bwcrypto::master_key(password, email, kdf)?;
+    Ok(SymmetricKey::expand(
+        &master_key,
+        b"bwfs-offline-enc",
+        b"bwfs-offline-mac",
+    ))
+}
+
+/// Vault contents being served from a snapshot.
+struct OfflineVault {
+    vault: Vault,
+    email: String,
+    created: OffsetDateTime,
+}
+
+/// Backend wrapper keeping an encrypted snapshot of the vault from the last refresh.
+///
+/// If unlocking the wrapped backend fails but the password decrypts the snapshot, the snapshot
+/// is served instead until the vault is locked.
+pub struct OfflineCache {
+    inner: Box<dyn VaultBackend>,
+    path: PathBuf,
+    /// Key and settings for writing snapshots, set after unlocking the inner backend.
+    key: Option<(SymmetricKey, String, Kdf)>,
+    offline: Option<OfflineVault>,
+}
+
+impl OfflineCache {
+    pub fn new(inner: Box<dyn VaultBackend>, path: PathBuf) -> Self {
+        Self {
+            inner,
+            path,
+            key: None,
+            offline: None,
+        }
+    }
+
+    fn save(&self, vault: &Vault) -> anyhow::Result<()> {
+        let Some((key, email, kdf)) = &self.key else {
+            return Ok(());
+        };
+        let snapshot = Snapshot {
+            email: email.clone(),
+            kdf: *kdf,
+            created: OffsetDateTime::now_utc(),
+            vault: key.encrypt_random(&serde_json::to_vec(vault)?)?,
+        };
+        snapshot.write(&self.path)?;
+        info!(path = ?self.path, "Saved offline snapshot");
+        Ok(())
+    }
+
+    fn unlock_offline(&mut self, password: &str) -> anyhow::Result<()> {
+        let snapshot = Snapshot::read(&self.path)?;
+        let key = snapshot_key(password, &snapshot.email, snapshot.kdf)?;
+        let vault: Vault = serde_json::from_slice(&key.decrypt(&snapshot.vault)?)?;
+        self.offline = Some(OfflineVault {
+            vault,
+            email: snapshot.email,
+            created: snapshot.created,
+        });
+        Ok(())
+    }
+}
+
+impl VaultBackend for OfflineCache {
+    fn status(&self) -> anyhow::Result<Status> {
+        match &self.offline {
+            Some(offline) => Ok(Status {
+                last_sync: offline.created,
+                user_email: offline.email.clone(),
+                user_id: Default::default(),
+                status: StatusKind::Unlocked,
+                server_url: None,
+            }),
+            None => self.inner.status(),
+        }
+    }
+
+    fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
+        match self.inner.unlock(password) {
+            Ok(()) => {
+                self.offline = None;
+                let email = self.inner.status()?.user_email;
+                let kdf = self
+                    .inner
+                    .kdf()
+                    .or_else(|| Snapshot::read(&self.path).ok().map(|s| s.kdf))
+                    .unwrap_or(DEFAULT_KDF);
+                debug!(?kdf, "Deriving offline snapshot key");
+                let key = snapshot_key(password, &email, kdf)?;
+                self.key = Some((key, email, kdf));
+                Ok(())
+            }
+            Err(error) => match self.unlock_offline(password) {
+                Ok(()) => {
+                    warn!(%error, "Failed to unlock, serving offline snapshot");
+                    Ok(())
+                }
+                Err(offline_error) => {
+                    debug!(%offline_error, "Failed to unlock offline snapshot");
+                    Err(error)
+                }
+            },
+        }
+    }
+
+    fn lock(&mut self) {
+        self.key = None;
+        self.offline = None;
+        self.inner.lock();
+    }
+
+    fn list_secrets(&self) -> anyhow::Result<SecretList> {
+        let vault = self.list_vault()?;
+        Ok(SecretList {
+            secrets: vault.secrets,
+            errors: vault.errors,
+        })
+    }
+
+    fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
+        Ok(self.list_vault()?.folders)
+    }
+
+    fn list_vault(&self) -> anyhow::Result<Vault> {
+        if let Some(offline) = &self.offline {
+            return Ok(offline.vault.clone());
+        }
+        let vault = self.inner.list_vault()?;
+        if let Err(error) = self.save(&vault) {
+            warn!(%error, "Failed to save offline snapshot");
+        }
+        Ok(vault)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        if self.offline.is_some() {
+            anyhow::bail!("Serving an offline snapshot, lock and unlock to go online");
+        }
+        self.inner.sync()
+    }
+
+    fn edit(&self, secret: &Secret) -> anyhow::Result<Secret> {
+        if self.offline.is_some() {
+            anyhow::bail!("Serving an offline snapshot, lock and unlock to go online");
+        }
+        self.inner.edit(secret)
+    }
+
+    fn kdf(&self) -> Option<Kdf> {
+        self.key.as_ref().map(|k| k.2)
+    }
+
+    fn offline_since(&self) -> Option<OffsetDateTime> {
+        self.offline.as_ref().map(|o| o.created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backend that can be switched between reachable and unreachable.
+    struct Stub {
+        online: bool,
+        unlocked: bool,
+    }
+
+    impl VaultBackend for Stub {
+        fn status(&self) -> anyhow::Result<Status> {
+            Ok(Status {
+                last_sync: OffsetDateTime::UNIX_EPOCH,
+                user_email: "user@example.com".to_owned(),
+                user_id: Default::default(),
+                status: if self.unlocked {
+                    StatusKind::Unlocked
+                } else {
+                    StatusKind::Locked
+                },
+                server_url: None,
+            })
+        }
+
+        fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
+            if !self.online {
+                anyhow::bail!("offline");
+            }
+            if password != "hunter2" {
+                anyhow::bail!("wrong password");
+            }
+            self.unlocked = true;
+            Ok(())
+        }
+
+        fn lock(&mut self) {
+            self.unlocked = false;
+        }
+
+        fn list_secrets(&self) -> anyhow::Result<SecretList> {
+            Ok(SecretList::default())
+        }
+
+        fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
+            if !self.unlocked {
+                anyhow::bail!("locked");
+            }
+            Ok(vec![Folder {
+                object: "folder".to_owned(),
+                id: None,
+                name: "No Folder".to_owned(),
+            }])
+        }
+
+        fn sync(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn edit(&self, _secret: &Secret) -> anyhow::Result<Secret> {
+            anyhow::bail!("unsupported")
+        }
+
+        fn kdf(&self) -> Option<Kdf> {
+            Some(Kdf::Pbkdf2 { iterations: 1000 })
+        }
+    }
+
+    fn cache(name: &str) -> (OfflineCache, PathBuf) {
+        let path = std::env::temp_dir().join(format!("bwfs-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let stub = Stub {
+            online: true,
+            unlocked: false,
+        };
+        (OfflineCache::new(Box::new(stub), path.clone()), path)
+    }
+
+    fn go_offline(cache: OfflineCache) -> OfflineCache {
+        OfflineCache::new(
+            Box::new(Stub {
+                online: false,
+                unlocked: false,
+            }),
+            cache.path.clone(),
+        )
+    }
+
+    #[test]
+    fn serves_snapshot_when_offline() {
+        let (mut cache, path) = cache("serves");
+        cache.unlock("hunter2").unwrap();
+        assert!(cache.offline_since().is_none());
+        let vault = cache.list_vault().unwrap();
+        assert_eq!(vault.folders.len(), 1);
+        assert!(path.exists());
+        cache.lock();
+
+        let mut cache = go_offline(cache);
+        cache.unlock("hunter2").unwrap();
+        assert!(cache.offline_since().is_some());
+        assert_eq!(cache.status().unwrap().status, StatusKind::Unlocked);
+        assert_eq!(cache.list_vault().unwrap().folders[0].name, "No Folder");
+
+        cache.lock();
+        assert!(cache.offline_since().is_none());
+        assert_eq!(cache.status().unwrap().status, StatusKind::Locked);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wrong_password_offline() {
+        let (mut cache, path) = cache("wrong");
+        cache.unlock("hunter2").unwrap();
+        cache.list_vault().unwrap();
+        cache.lock();
+
+        let mut cache = go_offline(cache);
+        assert_eq!(cache.unlock("hunter3").unwrap_err().to_string(), "offline");
+        assert!(cache.offline_since().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn snapshot_is_encrypted() {
+        let (mut cache, path) = cache("encrypted");
+        cache.unlock("hunter2").unwrap();
+        cache.list_vault().unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("No Folder"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
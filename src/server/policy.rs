@@ -0,0 +1,275 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use fuser::FileType;
+
+use super::layout::is_item_field;
+
+/// Fields secret unless configured otherwise, all of which the layouts have files for.
+pub const DEFAULT_SECRET_FIELDS: &str = "password,totp,card/code,private_key";
+
+/// Decides the mode and ownership of each entry in the filesystem.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Owner of entries without a more specific rule.
+    pub uid: u32,
+    /// Group of entries without a more specific rule.
+    pub gid: u32,
+    /// Mode applied to all directories.
+    pub dir_mode: u16,
+    /// Mode applied to files without a more specific rule.
+    pub file_mode: u16,
+    /// Mode applied to files holding secret fields.
+    pub secret_mode: u16,
+    /// Fields considered secret, such as `password`.
+    pub secret_fields: Vec<String>,
+    /// Per-field overrides, later rules take precedence.
+    pub rules: Vec<FieldRule>,
+}
+
+/// Ownership and mode for a single entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPermissions {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl EntryPermissions {
+    /// Whether a caller may access the entry with the given `mask` of `R_OK`, `W_OK` and `X_OK`.
+    ///
+    /// `gids` should contain the caller's primary and supplementary groups.
+    pub fn allows(&self, uid: u32, gids: &[u32], mask: i32) -> bool {
+        let mask = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u16;
+        if uid == 0 {
+            // root can read and write anything but only execute when someone can
+            return mask & libc::X_OK as u16 == 0 || self.mode & 0o111 != 0;
+        }
+        let granted = if uid == self.uid {
+            (self.mode >> 6) & 0o7
+        } else if gids.contains(&self.gid) {
+            (self.mode >> 3) & 0o7
+        } else {
+            self.mode & 0o7
+        };
+        granted & mask == mask
+    }
+}
+
+impl Policy {
+    /// Permissions for an entry of the given kind.
+    ///
+    /// `field` is the path of the entry relative to its item directory, e.g. `password` or
+    /// `uris/01`, and is empty for directories that are not part of an item. Values the vault
+    /// hides, like hidden custom fields, are `hidden` and so secret whatever their field.
+    pub fn permissions(&self, kind: FileType, field: &str, hidden: bool) -> EntryPermissions {
+        let mut perms = EntryPermissions {
+            mode: self.file_mode,
+            uid: self.uid,
+            gid: self.gid,
+        };
+        if kind == FileType::Directory {
+            perms.mode = self.dir_mode;
+            return perms;
+        }
+        if hidden || self.is_secret(field) {
+            perms.mode = self.secret_mode;
+        }
+        for rule in self.rules.iter().filter(|r| field_matches(&r.field, field)) {
+            perms.mode = rule.mode;
+            if let Some(uid) = rule.uid {
+                perms.uid = uid;
+            }
+            if let Some(gid) = rule.gid {
+                perms.gid = gid;
+            }
+        }
+        perms
+    }
+
+    /// Whether the field holds a secret value.
+    ///
+    /// Files holding a whole item are secret as long as any field is.
+    pub fn is_secret(&self, field: &str) -> bool {
+        (is_item_field(field) && !self.secret_fields.is_empty())
+            || self.secret_fields.iter().any(|s| field_matches(s, field))
+    }
+}
+
+/// A rule matches the field itself and anything nested under it, so `uris` matches `uris/01`.
+fn field_matches(rule: &str, field: &str) -> bool {
+    field == rule
+        || field
+            .strip_prefix(rule)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Override of the permissions for a field, in the form `FIELD=MODE[:USER[:GROUP]]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldRule {
+    pub field: String,
+    pub mode: u16,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Unresolved form of a [`FieldRule`], with the user and group given by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldRuleSpec {
+    pub field: String,
+    pub mode: u16,
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+/// Parse access controls in octal form, like `440`.
+pub fn parse_mode(s: &str) -> anyhow::Result<u16> {
+    let mode = u16::from_str_radix(s, 8).map_err(|e| anyhow::anyhow!("Invalid mode {s:?}: {e}"))?;
+    if mode > 0o7777 {
+        anyhow::bail!("Invalid mode {s:?}: must be at most 7777");
+    }
+    Ok(mode)
+}
+
+impl FromStr for FieldRuleSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, rest) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Field rule {s:?} must be FIELD=MODE[:USER[:GROUP]]"))?;
+        let mut parts = rest.split(':');
+        let mode = parts.next().unwrap_or_default();
+        let mode = parse_mode(mode).with_context(|| format!("Invalid field rule {s:?}"))?;
+        let user = parts.next().filter(|u| !u.is_empty()).map(str::to_owned);
+        let group = parts.next().filter(|g| !g.is_empty()).map(str::to_owned);
+        if parts.next().is_some() {
+            anyhow::bail!("Field rule {s:?} must be FIELD=MODE[:USER[:GROUP]]");
+        }
+        Ok(Self {
+            field: field.trim_matches('/').to_owned(),
+            mode,
+            user,
+            group,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: u32 = 1000;
+    const OTHER: u32 = 1001;
+    const SERVICES: u32 = 200;
+
+    fn policy() -> Policy {
+        Policy {
+            uid: OWNER,
+            gid: SERVICES,
+            dir_mode: 0o550,
+            file_mode: 0o440,
+            secret_mode: 0o400,
+            secret_fields: vec!["password".to_owned(), "card/code".to_owned()],
+            rules: vec![FieldRule {
+                field: "notes".to_owned(),
+                mode: 0o400,
+                uid: None,
+                gid: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn owner_can_read_secrets() {
+        let perms = policy().permissions(FileType::RegularFile, "password", false);
+        assert!(perms.allows(OWNER, &[OWNER], libc::R_OK));
+    }
+
+    #[test]
+    fn group_denied_secrets() {
+        let policy = policy();
+        for field in ["password", "card/code", "notes"] {
+            let perms = policy.permissions(FileType::RegularFile, field, false);
+            assert!(!perms.allows(OTHER, &[SERVICES], libc::R_OK), "{field}");
+        }
+    }
+
+    #[test]
+    fn group_can_read_plain_fields() {
+        let policy = policy();
+        for field in ["username", "uris/01"] {
+            let perms = policy.permissions(FileType::RegularFile, field, false);
+            assert!(
+                perms.allows(OTHER, &[OTHER, SERVICES], libc::R_OK),
+                "{field}"
+            );
+        }
+    }
+
+    #[test]
+    fn hidden_values_are_secret() {
+        let policy = policy();
+        let hidden = policy.permissions(FileType::RegularFile, "fields/pin", true);
+        assert_eq!(hidden.mode, 0o400);
+        let text = policy.permissions(FileType::RegularFile, "fields/pin", false);
+        assert_eq!(text.mode, 0o440);
+    }
+
+    #[test]
+    fn other_users_denied() {
+        let policy = policy();
+        let dir = policy.permissions(FileType::Directory, "", false);
+        assert!(!dir.allows(OTHER, &[OTHER], libc::R_OK));
+        assert!(!dir.allows(OTHER, &[OTHER], libc::X_OK));
+        let file = policy.permissions(FileType::RegularFile, "username", false);
+        assert!(!file.allows(OTHER, &[OTHER], libc::R_OK));
+    }
+
+    #[test]
+    fn writes_denied() {
+        let perms = policy().permissions(FileType::RegularFile, "username", false);
+        assert!(!perms.allows(OWNER, &[OWNER], libc::W_OK));
+        assert!(!perms.allows(OTHER, &[SERVICES], libc::R_OK | libc::W_OK));
+    }
+
+    #[test]
+    fn directories_traversable_by_group() {
+        let perms = policy().permissions(FileType::Directory, "", false);
+        assert!(perms.allows(OTHER, &[SERVICES], libc::R_OK | libc::X_OK));
+    }
+
+    #[test]
+    fn field_rules_match_nested() {
+        assert!(field_matches("uris", "uris/01"));
+        assert!(field_matches("uris", "uris"));
+        assert!(!field_matches("uris", "urisx"));
+        assert!(!field_matches("card/code", "card"));
+    }
+
+    #[test]
+    fn parse_field_rule() {
+        let rule: FieldRuleSpec = "uris=440::services".parse().unwrap();
+        assert_eq!(
+            rule,
+            FieldRuleSpec {
+                field: "uris".to_owned(),
+                mode: 0o440,
+                user: None,
+                group: Some("services".to_owned()),
+            }
+        );
+        assert!("uris".parse::<FieldRuleSpec>().is_err());
+        assert!("uris=999".parse::<FieldRuleSpec>().is_err());
+    }
+
+    #[test]
+    fn parse_octal_modes() {
+        assert_eq!(parse_mode("440").unwrap(), 0o440);
+        assert_eq!(parse_mode("0750").unwrap(), 0o750);
+        assert!(parse_mode("0o400").is_err());
+        assert!(parse_mode("9").is_err());
+        assert!(parse_mode("").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tracing::{debug, info, warn};
+
+/// Restricts items matching `path` to being read by the listed executables.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccessRule {
+    /// Glob over item paths, e.g. `ssh/**`, or the paths of their fields, e.g.
+    /// `prod/*/password`.
+    ///
+    /// Paths are those of the nested layout, whatever the layout being served.
+    pub path: String,
+    /// Globs over executable paths that may read matching items, e.g. `/usr/bin/ssh`.
+    pub exe: Vec<String>,
+}
+
+/// Per-process allowlist loaded from a JSON file of [`AccessRule`]s.
+///
+/// Items that match no rule can be read by any process. The file is reloaded whenever it changes.
+#[derive(Debug)]
+pub struct AccessRules {
+    file: PathBuf,
+    modified: Option<SystemTime>,
+    rules: Vec<AccessRule>,
+}
+
+impl AccessRules {
+    pub fn load(file: PathBuf) -> anyhow::Result<Self> {
+        let mut rules = Self {
+            file,
+            modified: None,
+            rules: Vec::new(),
+        };
+        rules.reload()?;
+        Ok(rules)
+    }
+
+    fn reload(&mut self) -> anyhow::Result<()> {
+        let modified = std::fs::metadata(&self.file)?.modified()?;
+        let content = std::fs::read_to_string(&self.file)?;
+        self.rules = serde_json::from_str(&content)?;
+        self.modified = Some(modified);
+        info!(file = ?self.file, rules = self.rules.len(), "Loaded access rules");
+        Ok(())
+    }
+
+    fn reload_if_changed(&mut self) {
+        let modified = std::fs::metadata(&self.file).and_then(|m| m.modified());
+        if modified.ok() != self.modified {
+            // keep the old rules if the new ones are broken
+            if let Err(error) = self.reload() {
+                warn!(%error, file = ?self.file, "Failed to reload access rules");
+            }
+        }
+    }
+
+    /// Whether process `pid` may read `fields` of the item at `item_path`.
+    ///
+    /// A rule applies if it matches the item path or that of one of the fields below it, e.g.
+    /// `prod/db/password`. The process is allowed if it, or any of its ancestors, runs an
+    /// allowed executable.
+    pub fn allows(&mut self, item_path: &str, fields: &[String], pid: u32) -> bool {
+        self.reload_if_changed();
+        let matching: Vec<_> = self
+            .rules
+            .iter()
+            .filter(|r| {
+                glob_match(&r.path, item_path)
+                    || fields
+                        .iter()
+                        .any(|field| glob_match(&r.path, &format!("{item_path}/{field}")))
+            })
+            .collect();
+        if matching.is_empty() {
+            return true;
+        }
+        let exes = process_ancestry_exes(pid);
+        debug!(item_path, ?exes, "Checking access rules");
+        matching.iter().any(|r| {
+            r.exe
+                .iter()
+                .any(|allowed| exes.iter().any(|exe| glob_match(allowed, exe)))
+        })
+    }
+}
+
+/// Executables of a process and all of its ancestors.
+fn process_ancestry_exes(pid: u32) -> Vec<String> {
+    let mut exes = Vec::new();
+    let mut pid = pid;
+    while pid > 0 {
+        if let Ok(exe) = std::fs::read_link(format!("/proc/{pid}/exe")) {
+            exes.push(exe.to_string_lossy().into_owned());
+        }
+        match parent_pid(pid) {
+            Some(ppid) if ppid != pid => pid = ppid,
+            _ => break,
+        }
+    }
+    exes
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat =
+        std::fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("stat")).ok()?;
+    // the command name can contain spaces and parens so skip past its closing paren
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Match a path against a glob where `*` and `?` stay within a `/` separated segment and `**`
+/// matches any number of whole segments.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<_> = pattern.trim_matches('/').split('/').collect();
+    let path: Vec<_> = path.trim_matches('/').split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_segment(segment.as_bytes(), name.as_bytes())
+                    && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_literal() {
+        assert!(glob_match("/usr/bin/ssh", "/usr/bin/ssh"));
+        assert!(!glob_match("/usr/bin/ssh", "/usr/bin/sshd"));
+    }
+
+    #[test]
+    fn glob_star_stays_in_segment() {
+        assert!(glob_match("ssh/*", "ssh/github"));
+        assert!(!glob_match("ssh/*", "ssh/work/github"));
+        assert!(glob_match("/usr/bin/ssh*", "/usr/bin/ssh-add"));
+        assert!(glob_match("prod/db-?", "prod/db-1"));
+        assert!(!glob_match("prod/db-?", "prod/db-12"));
+    }
+
+    #[test]
+    fn glob_double_star() {
+        assert!(glob_match("prod/**", "prod/db"));
+        assert!(glob_match("prod/**", "prod/eu/db"));
+        assert!(!glob_match("prod/**", "staging/db"));
+        assert!(glob_match("**/db", "prod/eu/db"));
+        assert!(glob_match("**", "anything/at/all"));
+    }
+
+    #[test]
+    fn unmatched_items_allowed() {
+        let mut rules = AccessRules {
+            file: PathBuf::from("/nonexistent"),
+            modified: None,
+            rules: vec![AccessRule {
+                path: "ssh/**".to_owned(),
+                exe: vec!["/nonexistent/ssh".to_owned()],
+            }],
+        };
+        let pid = std::process::id();
+        let fields = ["username".to_owned(), "password".to_owned()];
+        assert!(rules.allows("web/github", &fields, pid));
+        assert!(!rules.allows("ssh/github", &fields, pid));
+        assert!(!rules.allows("ssh/github", &[], pid));
+    }
+
+    #[test]
+    fn rules_match_fields() {
+        let mut rules = AccessRules {
+            file: PathBuf::from("/nonexistent"),
+            modified: None,
+            rules: vec![AccessRule {
+                path: "web/*/password".to_owned(),
+                exe: vec!["/nonexistent/deploy".to_owned()],
+            }],
+        };
+        let pid = std::process::id();
+        assert!(rules.allows("web/github", &["username".to_owned()], pid));
+        assert!(!rules.allows("web/github", &["password".to_owned()], pid));
+        assert!(!rules.allows(
+            "web/github",
+            &["username".to_owned(), "password".to_owned()],
+            pid
+        ));
+    }
+
+    #[test]
+    fn ancestors_included() {
+        let exes = process_ancestry_exes(std::process::id());
+        let own = std::env::current_exe().unwrap();
+        assert_eq!(exes.first().map(PathBuf::from), Some(own));
+    }
+}
@@ -0,0 +1,355 @@
+//! An `ssh-agent` serving the SSH key items in the vault.
+//!
+//! Implements the parts of the agent protocol needed to authenticate with keys: listing
+//! identities and signing. Keys can't be added or removed through the agent, the vault is the
+//! only source of them.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use rsa::{
+    pkcs1v15::SigningKey,
+    signature::{SignatureEncoding, Signer},
+};
+use sha2::{Sha256, Sha512};
+use ssh_key::{private::KeypairData, Algorithm, HashAlg, PrivateKey, PublicKey, Signature};
+use tracing::{debug, info, warn};
+
+use super::backend::VaultBackend;
+use super::mapfs::{Caller, MapFSRef};
+use super::{bind_socket_or_remove, check_read, peer_caller};
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Largest message accepted from a client, as in OpenSSH's agent.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// Listen for agent connections on `socket` in the background.
+pub fn spawn(
+    socket: PathBuf,
+    backend: Arc<Mutex<Box<dyn VaultBackend>>>,
+    fs: MapFSRef,
+) -> anyhow::Result<()> {
+    let listener = bind_socket_or_remove(socket.display().to_string())?;
+    info!(?socket, "Listening for ssh-agent connections");
+    std::thread::Builder::new()
+        .name("ssh-agent".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        warn!(%error, "Failed to accept ssh-agent connection");
+                        continue;
+                    }
+                };
+                debug!("Accepted ssh-agent connection");
+                let backend = Arc::clone(&backend);
+                let fs = fs.clone();
+                // clients keep the connection open for several requests
+                let res = std::thread::Builder::new()
+                    .name("ssh-agent-conn".to_owned())
+                    .spawn(move || {
+                        if let Err(error) = handle_connection(stream, &backend, &fs) {
+                            warn!(%error, "ssh-agent connection failed");
+                        }
+                    });
+                if let Err(error) = res {
+                    warn!(%error, "Failed to spawn ssh-agent connection thread");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle_connection(
+    mut stream: UnixStream,
+    backend: &Arc<Mutex<Box<dyn VaultBackend>>>,
+    fs: &MapFSRef,
+) -> anyhow::Result<()> {
+    let caller = match peer_caller(&stream) {
+        Ok(caller) => Some(caller),
+        Err(error) => {
+            warn!(%error, "Failed to get ssh-agent client credentials");
+            None
+        }
+    };
+    loop {
+        let mut len = [0; 4];
+        match stream.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            anyhow::bail!("Invalid message length {len}");
+        }
+        let mut message = vec![0; len];
+        stream.read_exact(&mut message)?;
+
+        let response = match check_read(caller, backend) {
+            Ok(caller) => handle_message(&message, &caller, fs),
+            Err(_) if message[0] == SSH_AGENTC_REQUEST_IDENTITIES => {
+                // nothing to offer while locked
+                identities_answer(&[])
+            }
+            Err(_) => {
+                debug!("Vault is locked, refusing ssh-agent request");
+                vec![SSH_AGENT_FAILURE]
+            }
+        };
+        let mut out = Vec::with_capacity(response.len() + 4);
+        put_string(&mut out, &response);
+        stream.write_all(&out)?;
+    }
+}
+
+fn handle_message(message: &[u8], caller: &Caller, fs: &MapFSRef) -> Vec<u8> {
+    let (kind, mut body) = (message[0], &message[1..]);
+    match kind {
+        SSH_AGENTC_REQUEST_IDENTITIES => {
+            debug!(?caller, "Listing ssh-agent identities");
+            let identities: Vec<_> = fs
+                .ssh_keys(caller)
+                .into_iter()
+                .filter_map(|(path, public_key)| match key_blob(&public_key) {
+                    Ok(blob) => Some((blob, path)),
+                    Err(error) => {
+                        warn!(path, %error, "Failed to parse SSH public key");
+                        None
+                    }
+                })
+                .collect();
+            identities_answer(&identities)
+        }
+        SSH_AGENTC_SIGN_REQUEST => {
+            let request = get_string(&mut body).and_then(|blob| {
+                let data = get_string(&mut body)?;
+                let flags = get_u32(&mut body)?;
+                Some((blob, data, flags))
+            });
+            let Some((blob, data, flags)) = request else {
+                warn!("Malformed ssh-agent sign request");
+                return vec![SSH_AGENT_FAILURE];
+            };
+            match sign_request(caller, fs, blob, data, flags) {
+                Ok(signature) => {
+                    let mut out = vec![SSH_AGENT_SIGN_RESPONSE];
+                    put_string(&mut out, &signature);
+                    out
+                }
+                Err(error) => {
+                    warn!(%error, "Refused ssh-agent sign request");
+                    vec![SSH_AGENT_FAILURE]
+                }
+            }
+        }
+        kind => {
+            debug!(kind, "Unsupported ssh-agent request");
+            vec![SSH_AGENT_FAILURE]
+        }
+    }
+}
+
+fn identities_answer(identities: &[(Vec<u8>, String)]) -> Vec<u8> {
+    let mut out = vec![SSH_AGENT_IDENTITIES_ANSWER];
+    out.extend_from_slice(&(identities.len() as u32).to_be_bytes());
+    for (blob, comment) in identities {
+        put_string(&mut out, blob);
+        put_string(&mut out, comment.as_bytes());
+    }
+    out
+}
+
+/// Sign `data` with the vault key matching `blob`, reading its private key as `caller`.
+fn sign_request(
+    caller: &Caller,
+    fs: &MapFSRef,
+    blob: &[u8],
+    data: &[u8],
+    flags: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let path = fs
+        .ssh_keys(caller)
+        .into_iter()
+        .find(|(_, public_key)| key_blob(public_key).is_ok_and(|b| b == blob))
+        .map(|(path, _)| path)
+        .ok_or_else(|| anyhow::anyhow!("No readable key matches the request"))?;
+    info!(path, ?caller, "Signing with SSH key");
+    // goes through the same checks, approval and audit as reading the file
+    let private_key = fs
+        .read_field(caller, &path, "private_key")
+        .map_err(|errno| anyhow::anyhow!("{path}: {}", std::io::Error::from_raw_os_error(errno)))?;
+    sign(&private_key, data, flags)
+}
+
+/// Sign `data`, returning the signature in the SSH wire format.
+fn sign(private_key: &str, data: &[u8], flags: u32) -> anyhow::Result<Vec<u8>> {
+    let key = PrivateKey::from_openssh(private_key)?;
+    if key.is_encrypted() {
+        anyhow::bail!("Encrypted private keys are not supported");
+    }
+    let signature = match key.key_data() {
+        // the key can sign with SHA-2 of either size, whichever the client asked for
+        KeypairData::Rsa(keypair) => {
+            let (hash, bytes) = if flags & SSH_AGENT_RSA_SHA2_512 != 0 {
+                let key = SigningKey::<Sha512>::try_from(keypair)?;
+                (HashAlg::Sha512, key.try_sign(data)?.to_vec())
+            } else if flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+                let key = SigningKey::<Sha256>::try_from(keypair)?;
+                (HashAlg::Sha256, key.try_sign(data)?.to_vec())
+            } else {
+                anyhow::bail!("SHA-1 RSA signatures are not supported");
+            };
+            Signature::new(Algorithm::Rsa { hash: Some(hash) }, bytes)?
+        }
+        _ => key.try_sign(data)?,
+    };
+    let mut out = Vec::new();
+    put_string(&mut out, signature.algorithm().as_str().as_bytes());
+    put_string(&mut out, signature.as_bytes());
+    Ok(out)
+}
+
+/// The wire encoding of an OpenSSH public key, identifying it in requests.
+fn key_blob(public_key: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(PublicKey::from_openssh(public_key.trim())?.to_bytes()?)
+}
+
+fn put_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+fn get_u32(input: &mut &[u8]) -> Option<u32> {
+    let (value, rest) = input.split_first_chunk::<4>()?;
+    *input = rest;
+    Some(u32::from_be_bytes(*value))
+}
+
+fn get_string<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = get_u32(input)? as usize;
+    if input.len() < len {
+        return None;
+    }
+    let (value, rest) = input.split_at(len);
+    *input = rest;
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::ScriptedBackend;
+    use crate::server::{
+        approval::Approver,
+        audit::{AuditLog, AuditLogRef, AuditSink},
+        layout::Layout,
+        mapfs::MapFS,
+        policy::Policy,
+        urimatch::UriMatcher,
+    };
+    use std::time::Duration;
+
+    fn unlocked_fs() -> MapFSRef {
+        let policy = Policy {
+            uid: 1000,
+            gid: 1000,
+            dir_mode: 0o550,
+            file_mode: 0o440,
+            secret_mode: 0o400,
+            secret_fields: vec!["private_key".to_owned()],
+            rules: Vec::new(),
+        };
+        let audit = AuditLogRef(Arc::new(Mutex::new(AuditLog::new(AuditSink::None))));
+        let approver = Approver::new(None, Duration::ZERO, Duration::ZERO);
+        let mut fs = MapFS::new(
+            policy,
+            Vec::new(),
+            audit,
+            approver,
+            None,
+            UriMatcher::default(),
+            Layout::default(),
+        );
+        fs.refresh(&ScriptedBackend::unlocked()).unwrap();
+        MapFSRef(Arc::new(Mutex::new(fs)))
+    }
+
+    fn request(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![kind];
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn lists_and_signs() {
+        let fs = unlocked_fs();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+
+        let answer = handle_message(&request(SSH_AGENTC_REQUEST_IDENTITIES, &[]), &owner, &fs);
+        let mut body = &answer[1..];
+        assert_eq!(answer[0], SSH_AGENT_IDENTITIES_ANSWER);
+        assert_eq!(get_u32(&mut body), Some(1));
+        let blob = get_string(&mut body).unwrap().to_vec();
+        assert_eq!(get_string(&mut body).unwrap(), b"web/deploy");
+
+        let mut sign = Vec::new();
+        put_string(&mut sign, &blob);
+        put_string(&mut sign, b"challenge");
+        sign.extend_from_slice(&0u32.to_be_bytes());
+        let response = handle_message(&request(SSH_AGENTC_SIGN_REQUEST, &sign), &owner, &fs);
+        assert_eq!(response[0], SSH_AGENT_SIGN_RESPONSE);
+        let mut body = &response[1..];
+        let mut signature = get_string(&mut body).unwrap();
+        let algorithm = std::str::from_utf8(get_string(&mut signature).unwrap()).unwrap();
+        let signature = Signature::new(
+            algorithm.parse().unwrap(),
+            get_string(&mut signature).unwrap(),
+        )
+        .unwrap();
+        let public_key = PublicKey::from_bytes(&blob).unwrap();
+        rsa::signature::Verifier::verify(public_key.key_data(), b"challenge", &signature).unwrap();
+
+        // other users see no keys and can't sign with them
+        let other = Caller {
+            uid: 2000,
+            gid: 2000,
+            ..owner
+        };
+        let answer = handle_message(&request(SSH_AGENTC_REQUEST_IDENTITIES, &[]), &other, &fs);
+        assert_eq!(answer, identities_answer(&[]));
+        let response = handle_message(&request(SSH_AGENTC_SIGN_REQUEST, &sign), &other, &fs);
+        assert_eq!(response, [SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn malformed_requests_fail() {
+        let fs = unlocked_fs();
+        let owner = Caller {
+            uid: 1000,
+            gid: 1000,
+            pid: std::process::id(),
+        };
+        let truncated = request(SSH_AGENTC_SIGN_REQUEST, &[0, 0, 0, 9, 1]);
+        assert_eq!(handle_message(&truncated, &owner, &fs), [SSH_AGENT_FAILURE]);
+        // adding keys isn't supported
+        assert_eq!(handle_message(&[17], &owner, &fs), [SSH_AGENT_FAILURE]);
+    }
+}
@@ -0,0 +1,84 @@
+use time::OffsetDateTime;
+
+use super::backend::VaultBackend;
+use super::bwclient::{Folder, Secret, SecretList, Status, StatusKind};
+
+/// Password the scripted vault unlocks with, matching the fake `bw` in `tests/fixtures`.
+pub const PASSWORD: &str = "hunter2";
+
+/// Backend serving the fixtures shared with the fake `bw` in `tests/fixtures`.
+#[derive(Default)]
+pub struct ScriptedBackend {
+    unlocked: bool,
+}
+
+impl ScriptedBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an already unlocked backend.
+    pub fn unlocked() -> Self {
+        let mut backend = Self::new();
+        backend.unlocked = true;
+        backend
+    }
+
+    fn ensure_unlocked(&self) -> anyhow::Result<()> {
+        if !self.unlocked {
+            anyhow::bail!("Vault is locked.");
+        }
+        Ok(())
+    }
+}
+
+impl VaultBackend for ScriptedBackend {
+    fn status(&self) -> anyhow::Result<Status> {
+        Ok(Status {
+            last_sync: OffsetDateTime::UNIX_EPOCH,
+            user_email: "user@example.com".to_owned(),
+            user_id: Default::default(),
+            status: if self.unlocked {
+                StatusKind::Unlocked
+            } else {
+                StatusKind::Locked
+            },
+            server_url: None,
+        })
+    }
+
+    fn unlock(&mut self, password: &str) -> anyhow::Result<()> {
+        if password != PASSWORD {
+            anyhow::bail!("Invalid master password.");
+        }
+        self.unlocked = true;
+        Ok(())
+    }
+
+    fn lock(&mut self) {
+        self.unlocked = false;
+    }
+
+    fn list_secrets(&self) -> anyhow::Result<SecretList> {
+        self.ensure_unlocked()?;
+        Ok(SecretList::parse(serde_json::from_str(include_str!(
+            "../../tests/fixtures/items.json"
+        ))?))
+    }
+
+    fn list_folders(&self) -> anyhow::Result<Vec<Folder>> {
+        self.ensure_unlocked()?;
+        Ok(serde_json::from_str(include_str!(
+            "../../tests/fixtures/folders.json"
+        ))?)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        self.ensure_unlocked()
+    }
+
+    fn edit(&self, secret: &Secret) -> anyhow::Result<Secret> {
+        self.ensure_unlocked()?;
+        Ok(secret.clone())
+    }
+}
@@ -0,0 +1,253 @@
+//! Matching URLs against the URIs saved with logins, following the Bitwarden clients.
+
+use std::{collections::HashSet, net::IpAddr, path::Path};
+
+use regex::RegexBuilder;
+use tracing::{debug, warn};
+use url::Url;
+
+use super::bwclient::{SecretLoginUri, UriMatch};
+
+/// Where distributions install the public suffix list.
+pub const DEFAULT_PUBLIC_SUFFIX_LIST: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+/// Rules from the public suffix list, see <https://publicsuffix.org/list/>.
+///
+/// An empty list treats only the last label of a host as its suffix.
+#[derive(Debug, Clone, Default)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    /// Suffixes with a `*.` rule, stored without it.
+    wildcards: HashSet<String>,
+    /// Suffixes with a `!` rule, stored without it.
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    pub fn parse(list: &str) -> Self {
+        let mut psl = Self::default();
+        for line in list.lines() {
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            let rule = rule.to_lowercase();
+            if let Some(exception) = rule.strip_prefix('!') {
+                psl.exceptions.insert(exception.to_owned());
+            } else if let Some(wildcard) = rule.strip_prefix("*.") {
+                psl.wildcards.insert(wildcard.to_owned());
+            } else {
+                psl.rules.insert(rule);
+            }
+        }
+        psl
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Number of labels at the end of `labels` making up their public suffix.
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        // the longest matching rule wins, so go from the whole host down
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return labels.len() - i - 1;
+            }
+            if self.rules.contains(&candidate)
+                || (i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].join(".")))
+            {
+                return labels.len() - i;
+            }
+        }
+        // the implicit `*` rule
+        1
+    }
+
+    /// The registrable domain of `host`, its public suffix and one more label.
+    ///
+    /// IP addresses and single label hosts like `localhost` are their own base domain, a host
+    /// that is itself a public suffix has none.
+    pub fn base_domain(&self, host: &str) -> Option<String> {
+        let host = host.trim_end_matches('.').to_lowercase();
+        let bare = host.trim_start_matches('[').trim_end_matches(']');
+        if bare.parse::<IpAddr>().is_ok() || !host.contains('.') {
+            return Some(host);
+        }
+        let labels: Vec<_> = host.split('.').collect();
+        let suffix_len = self.suffix_len(&labels);
+        (labels.len() > suffix_len).then(|| labels[labels.len() - suffix_len - 1..].join("."))
+    }
+}
+
+/// Decides whether login URIs match a URL, by the match setting of each URI.
+#[derive(Debug, Clone, Default)]
+pub struct UriMatcher {
+    psl: PublicSuffixList,
+}
+
+impl UriMatcher {
+    pub fn new(psl: PublicSuffixList) -> Self {
+        Self { psl }
+    }
+
+    /// Create a matcher with the public suffix list at `path`.
+    ///
+    /// Falls back to an empty list if it can't be read, so that `example.co.uk` and
+    /// `other.co.uk` would be treated as the same domain.
+    pub fn load(path: &Path) -> Self {
+        match PublicSuffixList::load(path) {
+            Ok(psl) => Self::new(psl),
+            Err(error) => {
+                warn!(?path, %error, "Failed to load public suffix list, using only the last label");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn base_domain(&self, host: &str) -> Option<String> {
+        self.psl.base_domain(host)
+    }
+
+    /// Whether the saved login `uri` matches `url`.
+    ///
+    /// URIs without a match setting use the default of matching on the base domain.
+    pub fn matches(&self, uri: &SecretLoginUri, url: &str) -> bool {
+        match uri.r#match.unwrap_or(UriMatch::Domain) {
+            UriMatch::Domain => {
+                let domain = |s: &str| {
+                    parse_uri(s)
+                        .and_then(|u| u.host_str().map(str::to_owned))
+                        .and_then(|h| self.base_domain(&h))
+                };
+                domain(&uri.uri).is_some_and(|d| domain(url) == Some(d))
+            }
+            UriMatch::Host => {
+                let host =
+                    |s: &str| parse_uri(s).and_then(|u| Some((u.host_str()?.to_owned(), u.port())));
+                host(&uri.uri).is_some_and(|h| host(url) == Some(h))
+            }
+            UriMatch::StartsWith => url.starts_with(&uri.uri),
+            UriMatch::Exact => url == uri.uri,
+            UriMatch::RegularExpression => {
+                match RegexBuilder::new(&uri.uri).case_insensitive(true).build() {
+                    Ok(regex) => regex.is_match(url),
+                    Err(error) => {
+                        debug!(uri.uri, %error, "Invalid regular expression in login URI");
+                        false
+                    }
+                }
+            }
+            UriMatch::Never | UriMatch::Other(_) => false,
+        }
+    }
+}
+
+/// Hostname of a login URI, lowercased and without a trailing dot.
+pub fn uri_host(uri: &str) -> Option<String> {
+    let url = parse_uri(uri)?;
+    Some(url.host_str()?.trim_end_matches('.').to_lowercase())
+}
+
+/// Parse a URI as saved with a login, which may leave out the scheme.
+fn parse_uri(uri: &str) -> Option<Url> {
+    match Url::parse(uri) {
+        Ok(url) if url.has_host() => Some(url),
+        _ if !uri.contains("://") => Url::parse(&format!("http://{uri}")).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "// comment\ncom\nuk\nco.uk\n*.ck\n!www.ck\n";
+
+    fn uri(uri: &str, r#match: Option<UriMatch>) -> SecretLoginUri {
+        SecretLoginUri {
+            r#match,
+            uri: uri.to_owned(),
+        }
+    }
+
+    #[test]
+    fn base_domains() {
+        let psl = PublicSuffixList::parse(LIST);
+        assert_eq!(
+            psl.base_domain("a.b.example.co.uk").as_deref(),
+            Some("example.co.uk")
+        );
+        assert_eq!(
+            psl.base_domain("Git.Example.com.").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(psl.base_domain("co.uk"), None);
+        assert_eq!(psl.base_domain("a.b.ck").as_deref(), Some("a.b.ck"));
+        assert_eq!(psl.base_domain("a.www.ck").as_deref(), Some("www.ck"));
+        assert_eq!(psl.base_domain("localhost").as_deref(), Some("localhost"));
+        assert_eq!(psl.base_domain("10.0.0.1").as_deref(), Some("10.0.0.1"));
+        // without a list only the last label is a suffix
+        assert_eq!(
+            PublicSuffixList::default()
+                .base_domain("a.example.co.uk")
+                .as_deref(),
+            Some("co.uk")
+        );
+    }
+
+    #[test]
+    fn match_settings() {
+        let matcher = UriMatcher::new(PublicSuffixList::parse(LIST));
+        let url = "https://git.example.co.uk:8443/team/repo.git";
+
+        assert!(matcher.matches(&uri("example.co.uk", None), url));
+        assert!(matcher.matches(
+            &uri("https://www.example.co.uk", Some(UriMatch::Domain)),
+            url
+        ));
+        assert!(!matcher.matches(&uri("https://other.co.uk", None), url));
+
+        assert!(matcher.matches(
+            &uri("https://git.example.co.uk:8443/", Some(UriMatch::Host)),
+            url
+        ));
+        assert!(!matcher.matches(&uri("https://git.example.co.uk", Some(UriMatch::Host)), url));
+
+        assert!(matcher.matches(
+            &uri(
+                "https://git.example.co.uk:8443/team/",
+                Some(UriMatch::StartsWith)
+            ),
+            url
+        ));
+        assert!(!matcher.matches(
+            &uri(
+                "https://git.example.co.uk:8443/other/",
+                Some(UriMatch::StartsWith)
+            ),
+            url
+        ));
+
+        assert!(matcher.matches(&uri(url, Some(UriMatch::Exact)), url));
+        assert!(!matcher.matches(
+            &uri("https://git.example.co.uk", Some(UriMatch::Exact)),
+            url
+        ));
+
+        assert!(matcher.matches(
+            &uri(
+                r"^https://GIT\.example\.co\.uk(:\d+)?/",
+                Some(UriMatch::RegularExpression)
+            ),
+            url
+        ));
+        assert!(!matcher.matches(&uri("(", Some(UriMatch::RegularExpression)), url));
+
+        assert!(!matcher.matches(&uri(url, Some(UriMatch::Never)), url));
+        assert!(!matcher.matches(&uri(url, Some(UriMatch::Other(9))), url));
+    }
+}
@@ -0,0 +1,156 @@
+//! Templates with `{{ secret "PATH" }}` lookups of files in the vault.
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    /// Path of a file relative to the mountpoint.
+    Secret(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let offset = source.len() - rest.len() + start;
+            let line = source[..offset].matches('\n').count() + 1;
+            let inner = &rest[start + 2..];
+            // parse the quoted path before looking for the end, which it may contain
+            let parsed = parse_secret(inner)
+                .and_then(|(path, after)| Some((path, after.trim_start().strip_prefix("}}")?)));
+            if let Some((path, after)) = parsed {
+                segments.push(Segment::Secret(path));
+                rest = after;
+                continue;
+            }
+            let Some(end) = inner.find("}}") else {
+                anyhow::bail!("line {line}: unclosed {{{{");
+            };
+            let expr = inner[..end].trim();
+            anyhow::bail!("line {line}: unknown expression {expr:?}");
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        Ok(Self { segments })
+    }
+
+    /// All paths looked up by the template.
+    pub fn paths(&self) -> BTreeSet<&str> {
+        self.segments
+            .iter()
+            .filter_map(|s| match s {
+                Segment::Secret(path) => Some(path.as_str()),
+                Segment::Text(_) => None,
+            })
+            .collect()
+    }
+
+    /// Render the template, getting the value of each path with `lookup`.
+    pub fn render(
+        &self,
+        mut lookup: impl FnMut(&str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Secret(path) => out.push_str(&lookup(path)?),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Parse `secret "PATH"` at the start of `source`, where the path may contain `\"` and `\\`
+/// escapes, returning the path and what follows the closing quote.
+fn parse_secret(source: &str) -> Option<(String, &str)> {
+    let quoted = source.trim_start().strip_prefix("secret")?.trim_start();
+    let mut chars = quoted.strip_prefix('"')?.chars();
+    let mut path = String::new();
+    loop {
+        match chars.next()? {
+            '"' => break,
+            '\\' => path.push(chars.next()?),
+            c => path.push(c),
+        }
+    }
+    if path.is_empty() {
+        None
+    } else {
+        Some((path, chars.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let template = Template::parse(
+            "machine db login {{ secret \"prod/db/username\" }} password {{secret \"prod/db/password\"}}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            template.paths().into_iter().collect::<Vec<_>>(),
+            ["prod/db/password", "prod/db/username"]
+        );
+        let out = template
+            .render(|path| Ok(path.rsplit('/').next().unwrap().to_uppercase()))
+            .unwrap();
+        assert_eq!(out, "machine db login USERNAME password PASSWORD\n");
+    }
+
+    #[test]
+    fn escapes() {
+        let template = Template::parse(r#"{{ secret "web/a \"b\"/c\\d" }}"#).unwrap();
+        assert_eq!(
+            template.paths().into_iter().collect::<Vec<_>>(),
+            [r#"web/a "b"/c\d"#]
+        );
+    }
+
+    #[test]
+    fn braces_in_path() {
+        let template = Template::parse("x{{ secret \"a}}b/password\" }}y").unwrap();
+        assert_eq!(
+            template.paths().into_iter().collect::<Vec<_>>(),
+            ["a}}b/password"]
+        );
+        let out = template.render(|path| Ok(path.to_uppercase())).unwrap();
+        assert_eq!(out, "xA}}B/PASSWORDy");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Template::parse("a\nb {{ secret \"x\"")
+                .unwrap_err()
+                .to_string(),
+            "line 2: unclosed {{"
+        );
+        assert_eq!(
+            Template::parse("{{ env \"HOME\" }}")
+                .unwrap_err()
+                .to_string(),
+            "line 1: unknown expression \"env \\\"HOME\\\"\""
+        );
+        assert!(Template::parse("{{ secret \"\" }}").is_err());
+        assert!(Template::parse("{{ secret \"a\" b }}").is_err());
+        let lookup_error = Template::parse("{{ secret \"a\" }}")
+            .unwrap()
+            .render(|_| anyhow::bail!("Vault is locked"));
+        assert!(lookup_error.is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! End to end tests running `bwfs serve` against the fake `bw` in `tests/fixtures`.
+//!
+//! These need FUSE, so are ignored by default. Run them with `cargo test -- --ignored`, which
+//! fails if `/dev/fuse` or `fusermount` aren't available.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+const BWFS: &str = env!("CARGO_BIN_EXE_bwfs");
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The `fusermount` binary to unmount with, panicking if FUSE isn't available.
+fn fusermount() -> &'static str {
+    assert!(Path::new("/dev/fuse").exists(), "/dev/fuse is unavailable");
+    ["fusermount3", "fusermount"]
+        .into_iter()
+        .find(|bin| {
+            Command::new(bin)
+                .arg("--version")
+                .output()
+                .is_ok_and(|o| o.status.success())
+        })
+        .expect("fusermount is unavailable")
+}
+
+/// A running `bwfs serve` with its own mountpoint and socket, cleaned up on drop.
+struct Server {
+    dir: PathBuf,
+    mountpoint: PathBuf,
+    socket: PathBuf,
+    child: Child,
+    fusermount: &'static str,
+}
+
+impl Server {
+    fn start(name: &str, fusermount: &'static str) -> Self {
+        let dir = std::env::temp_dir().join(format!("bwfs-test-{}-{name}", std::process::id()));
+        let mountpoint = dir.join("mnt");
+        let socket = dir.join("socket");
+        std::fs::create_dir_all(&mountpoint).unwrap();
+        let child = Command::new(BWFS)
+            .arg("--socket")
+            .arg(&socket)
+            .arg("serve")
+            .arg(&mountpoint)
+            .arg("--no-auto-unmount")
+            .arg("--lock-after-s=0")
+            .arg("--bw-bin")
+            .arg(fixture("bw"))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Self {
+            dir,
+            mountpoint,
+            socket,
+            child,
+            fusermount,
+        };
+        let start = Instant::now();
+        while !server.socket.exists() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "server didn't start"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    fn bwfs(&self, args: &[&str]) -> Output {
+        Command::new(BWFS)
+            .arg("--socket")
+            .arg(&self.socket)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn unlock(&self) -> Output {
+        let prompt = fixture("password.sh");
+        self.bwfs(&["unlock", "--password-prompt", prompt.to_str().unwrap()])
+    }
+
+    fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.mountpoint.join(path)).unwrap()
+    }
+
+    fn list(&self, path: &str) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(self.mountpoint.join(path))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = Command::new(self.fusermount)
+            .arg("-u")
+            .arg(&self.mountpoint)
+            .output();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn unlock_walk_and_lock() {
+    let fusermount = fusermount();
+    let server = Server::start("walk", fusermount);
+
+    assert_eq!(server.bwfs(&["status"]).status.code(), Some(1));
+    assert!(server.list("").is_empty());
+
+    let unlock = server.unlock();
+    assert!(unlock.status.success(), "{unlock:?}");
+    assert_eq!(server.bwfs(&["status"]).status.code(), Some(0));
+
+    assert_eq!(
+        server.list(""),
+        [
+            "No Folder",
+            "by-host",
+            "by-id",
+            "by-type",
+            "favorites",
+            "prod",
+            "recent",
+            "web"
+        ]
+    );
+    assert_eq!(
+        server.list("web/github"),
+        [
+            "fields",
+            "id",
+            "item.env",
+            "item.json",
+            "notes",
+            "password",
+            "type",
+            "uris",
+            "username"
+        ]
+    );
+    assert_eq!(server.read("web/github/username"), "octocat");
+    assert_eq!(
+        server.read("web/github/password"),
+        "correct horse battery staple"
+    );
+    assert_eq!(server.read("web/github/uris/01"), "https://github.com");
+    assert_eq!(server.read("web/github/fields/pin"), "1234");
+    assert_eq!(server.read("prod/eu/database/password"), "hunter2");
+    assert_eq!(server.read("prod/eu/database/fields/user"), "postgres");
+    assert_eq!(server.read("No Folder/wifi/notes"), "guest network");
+    assert_eq!(server.read("favorites/github/username"), "octocat");
+    assert_eq!(server.read("recent/01 database/username"), "postgres");
+
+    let lock = server.bwfs(&["lock"]);
+    assert!(lock.status.success(), "{lock:?}");
+    assert_eq!(server.bwfs(&["status"]).status.code(), Some(1));
+    assert!(server.list("").is_empty());
+    assert!(std::fs::read_to_string(server.mountpoint.join("web/github/password")).is_err());
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn refresh_while_locked_fails() {
+    let fusermount = fusermount();
+    let server = Server::start("locked", fusermount);
+
+    let refresh = server.bwfs(&["refresh"]);
+    assert!(String::from_utf8_lossy(&refresh.stderr).contains("Failed to refresh"));
+    assert!(server.list("").is_empty());
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn audit_records_reads() {
+    let fusermount = fusermount();
+    let server = Server::start("audit", fusermount);
+    assert!(server.unlock().status.success());
+
+    server.read("web/github/password");
+    let tail = server.bwfs(&["audit", "tail"]);
+    let tail = String::from_utf8_lossy(&tail.stdout);
+    assert!(tail.contains("item=0b8d6c1a-5e3f-4a2b-9c7d-8e9f0a1b2c3d field=password"));
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn exec_sets_env() {
+    let fusermount = fusermount();
+    let server = Server::start("exec", fusermount);
+
+    let locked = server.bwfs(&["exec", "--env", "PASS=web/github/password", "--", "true"]);
+    assert!(!locked.status.success());
+    assert!(String::from_utf8_lossy(&locked.stderr).contains("Vault is locked"));
+
+    assert!(server.unlock().status.success());
+    let exec = server.bwfs(&[
+        "exec",
+        "--env",
+        "PASS=web/github/password",
+        "--env",
+        "USER=prod/eu/database/username",
+        "--",
+        "sh",
+        "-c",
+        "echo \"$USER:$PASS\"",
+    ]);
+    assert!(exec.status.success(), "{exec:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&exec.stdout),
+        "postgres:correct horse battery staple\n"
+    );
+
+    let missing = server.bwfs(&["exec", "--env", "PASS=web/nope", "--", "true"]);
+    assert!(!missing.status.success());
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn render_template() {
+    let fusermount = fusermount();
+    let server = Server::start("render", fusermount);
+    assert!(server.unlock().status.success());
+
+    let template = server.dir.join("pgpass.tmpl");
+    let output = server.dir.join("pgpass");
+    std::fs::write(
+        &template,
+        "db:5432:*:{{ secret \"prod/eu/database/username\" }}:{{ secret \"prod/eu/database/password\" }}\n",
+    )
+    .unwrap();
+    let render = server.bwfs(&[
+        "render",
+        template.to_str().unwrap(),
+        "--output",
+        output.to_str().unwrap(),
+    ]);
+    assert!(render.status.success(), "{render:?}");
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "db:5432:*:postgres:hunter2\n"
+    );
+    let mode =
+        std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&output).unwrap().permissions());
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn get_over_socket() {
+    let fusermount = fusermount();
+    let server = Server::start("get", fusermount);
+
+    let locked = server.bwfs(&["get", "web/github/password"]);
+    assert!(!locked.status.success());
+    assert!(String::from_utf8_lossy(&locked.stderr).contains("Vault is locked"));
+
+    assert!(server.unlock().status.success());
+    let get = server.bwfs(&["get", "web/github/password"]);
+    assert!(get.status.success(), "{get:?}");
+    assert_eq!(get.stdout, b"correct horse battery staple");
+
+    let json = server.bwfs(&["get", "--json", "web/github"]);
+    assert!(json.status.success(), "{json:?}");
+    let item: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(item["login"]["username"], "octocat");
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn match_by_url() {
+    let fusermount = fusermount();
+    let server = Server::start("match", fusermount);
+    assert!(server.unlock().status.success());
+
+    let found = server.bwfs(&["match", "https://gist.github.com/octocat"]);
+    assert!(found.status.success(), "{found:?}");
+    assert_eq!(String::from_utf8_lossy(&found.stdout), "web/github\n");
+    assert!(!server
+        .bwfs(&["match", "https://gitlab.com"])
+        .status
+        .success());
+
+    assert_eq!(server.list("by-host/github.com"), ["github"]);
+    assert_eq!(
+        std::fs::read_link(server.mountpoint.join("by-host/github.com/github")).unwrap(),
+        Path::new("../../web/github")
+    );
+    assert_eq!(server.read("by-host/github.com/github/username"), "octocat");
+}
+
+#[test]
+#[ignore = "needs FUSE"]
+fn git_credential_helper() {
+    let fusermount = fusermount();
+    let server = Server::start("git", fusermount);
+    assert!(server.unlock().status.success());
+
+    let credential = |input: &str| {
+        let mut child = Command::new(BWFS)
+            .arg("--socket")
+            .arg(&server.socket)
+            .args(["git-credential", "get"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        credential("protocol=https\nhost=github.com\npath=octocat/repo.git\n\n"),
+        "username=octocat\npassword=correct horse battery staple\n"
+    );
+    assert_eq!(credential("protocol=https\nhost=gitlab.com\n\n"), "");
+    assert_eq!(
+        credential("protocol=https\nhost=github.com\nusername=someone\n\n"),
+        ""
+    );
+}